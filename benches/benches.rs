@@ -12,29 +12,21 @@ fn criterion_bench(c: &mut Criterion) {
         for _ in 0..1 {
             let key_len = r.gen_range(0, 100_001);
             let val_len = r.gen_range(0, 100_001);
-            let key: String = r
-                .sample_iter(&Alphanumeric)
-                .take(key_len)
-                .map(char::from)
-                .collect();
-            let val: String = r
-                .sample_iter(&Alphanumeric)
-                .take(val_len)
-                .map(char::from)
-                .collect();
+            let key: String = r.sample_iter(&Alphanumeric).take(key_len).collect();
+            let val: String = r.sample_iter(&Alphanumeric).take(val_len).collect();
             v.push((key, val));
         }
         let temp_dir = TempDir::new().expect("unable to create temporary working directory");
 
         b.iter(|| {
-            let mut store = KvStore::open(temp_dir.path()).expect("unable to open KvStore");
+            let store = KvStore::open(temp_dir.path()).expect("unable to open KvStore");
             for (key, val) in v.clone() {
                 store.set(key, val).expect("Store should not fail");
             }
         });
 
         b.iter(|| {
-            let mut store = KvStore::open(temp_dir.path()).expect("unable to open KvStore");
+            let store = KvStore::open(temp_dir.path()).expect("unable to open KvStore");
             for (key, val) in v.clone() {
                 let store_val = store
                     .get(key)
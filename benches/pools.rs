@@ -0,0 +1,68 @@
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput};
+use kvs::command::Request;
+use kvs::server::KvsServer;
+use kvs::thread_pool::{
+    NaiveThreadPool, RayonThreadPool, SharedQueueThreadPool, ThreadPool, WorkStealingThreadPool,
+};
+use kvs::{KvStore, KvsClient};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const CLIENTS: u64 = 8;
+const REQUESTS_PER_CLIENT: u64 = 50;
+
+/// Requests per second a `KvsServer` on pool `P` answers, with each client
+/// thread opening a connection per request
+fn bench_pool<P: ThreadPool + Send + 'static>(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    addr: SocketAddr,
+) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = KvsServer::new(
+        KvStore::open(temp_dir.path()).unwrap(),
+        P::new(4).unwrap(),
+        slog::Logger::root(slog::Discard, slog::o!()),
+    );
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            let clients: Vec<_> = (0..CLIENTS)
+                .map(|client| {
+                    thread::spawn(move || {
+                        for i in 0..REQUESTS_PER_CLIENT {
+                            let key = format!("client{}-key{}", client, i);
+                            KvsClient::send(Request::Set(key, "value".to_owned()), addr).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for client in clients {
+                client.join().unwrap();
+            }
+        })
+    });
+
+    handle.shutdown();
+    server.join().unwrap();
+}
+
+fn concurrent_clients(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent_clients");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(CLIENTS * REQUESTS_PER_CLIENT));
+    bench_pool::<NaiveThreadPool>(&mut group, "naive", "127.0.0.1:4191".parse().unwrap());
+    bench_pool::<SharedQueueThreadPool>(&mut group, "shared", "127.0.0.1:4192".parse().unwrap());
+    bench_pool::<RayonThreadPool>(&mut group, "rayon", "127.0.0.1:4193".parse().unwrap());
+    bench_pool::<WorkStealingThreadPool>(&mut group, "stealing", "127.0.0.1:4194".parse().unwrap());
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_clients);
+criterion_main!(benches);
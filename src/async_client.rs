@@ -0,0 +1,41 @@
+use crate::client::{closed_by_server, interpret, refusal_to_error};
+use crate::command::{Request, Response};
+use crate::frame;
+use crate::transport::Address;
+use crate::Result;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+/// The async counterpart of `KvsClient`, speaking the same wire protocol
+pub struct AsyncKvsClient {}
+
+impl AsyncKvsClient {
+    pub async fn send<A: Into<Address>>(request: Request, addr: A) -> Result<Option<String>> {
+        let response = AsyncKvsClient::call(request.clone(), addr).await?;
+        interpret(&request, response)
+    }
+
+    /// Sends `request` and returns the server's raw response, like `KvsClient::call`
+    pub async fn call<A: Into<Address>>(request: Request, addr: A) -> Result<Response> {
+        match addr.into() {
+            Address::Tcp(addr) => exchange(TcpStream::connect(addr).await?, request).await,
+            #[cfg(unix)]
+            Address::Unix(path) => exchange(UnixStream::connect(path).await?, request).await,
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into()),
+        }
+    }
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: Request,
+) -> Result<Response> {
+    frame::write_frame_async(&mut stream, &request).await?;
+    match frame::read_frame_async(&mut stream, frame::DEFAULT_MAX_FRAME_SIZE).await? {
+        Some(response) => refusal_to_error(response),
+        None => Err(closed_by_server()),
+    }
+}
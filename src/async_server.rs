@@ -0,0 +1,257 @@
+use crate::command::{Request, Response};
+use crate::engines::{AsyncKvsEngine, KvsEngine};
+use crate::error::Error;
+use crate::frame;
+use crate::logging::LogData;
+use crate::metrics::Metrics;
+use crate::rate_limit::Client;
+use crate::server::{
+    handle_request, overloaded, Limits, ReloadHandle, ServerConfig, REJECT_TIMEOUT,
+};
+use crate::shutdown::ShutdownHandle;
+use crate::Result;
+use slog::{error, info, o, warn};
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinSet;
+
+/// A `KvsServer` variant serving each connection as a tokio task instead of
+/// pinning a pool thread, so concurrency is not capped by a thread count.
+pub struct AsyncKvsServer<E: KvsEngine> {
+    engine: AsyncKvsEngine<E>,
+    logger: slog::Logger,
+    config: ServerConfig,
+    shutdown: ShutdownHandle,
+    metrics: Arc<Metrics>,
+    reload: ReloadHandle,
+}
+
+/// State every connection of one `start` shares
+struct Shared {
+    reload: ReloadHandle,
+    metrics: Arc<Metrics>,
+    log_data: LogData,
+    request_ids: AtomicU64,
+}
+
+impl<E: KvsEngine> AsyncKvsServer<E> {
+    pub fn new(engine: E, logger: slog::Logger) -> AsyncKvsServer<E> {
+        AsyncKvsServer::with_config(engine, logger, ServerConfig::default())
+    }
+
+    pub fn with_config(engine: E, logger: slog::Logger, config: ServerConfig) -> AsyncKvsServer<E> {
+        AsyncKvsServer {
+            engine: AsyncKvsEngine::new(engine),
+            logger,
+            reload: ReloadHandle::without_acls(&config),
+            config,
+            shutdown: ShutdownHandle::new(),
+            metrics: Arc::default(),
+        }
+    }
+
+    /// What the server records about the requests it serves, for `MetricsExporter`
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Returns a handle that makes `start` stop accepting, drain and return
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Returns a handle that changes the server's limits while it runs.
+    /// Reloads that set TLS or an ACL are refused, as they are not supported here yet.
+    pub fn reload_handle(&self) -> ReloadHandle {
+        self.reload.clone()
+    }
+
+    /// Serves TCP connections on `addr`. TLS and ACLs are not supported here yet.
+    pub async fn start<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
+        if self.config.tls.is_some() || self.config.acl.is_some() {
+            return Err(Error::Unsupported(
+                "the async server does not serve TLS or ACLs".to_owned(),
+            ));
+        }
+        let connection = TcpListener::bind(addr).await?;
+        self.shutdown
+            .register_listener(connection.local_addr()?.into());
+        info!(self.logger, "starting async server...");
+
+        let shared = Arc::new(Shared {
+            reload: self.reload.clone(),
+            metrics: self.metrics.clone(),
+            log_data: self.config.log_data,
+            request_ids: AtomicU64::new(0),
+        });
+        let mut connections = JoinSet::new();
+        loop {
+            let accepted = connection.accept().await;
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+            // Reap finished connections so the set does not grow without bound
+            while connections.try_join_next().is_some() {}
+
+            let engine = self.engine.clone();
+            let logger = self.logger.clone();
+            let limits = &self.reload.current().config.limits;
+            match accepted {
+                Ok((stream, _)) => match limits.max_connections {
+                    Some(max) if connections.len() >= max => {
+                        warn!(logger, "too many connections, rejecting"; "max_connections" => max);
+                        tokio::spawn(reject(stream, limits.clone()));
+                    }
+                    _ => {
+                        connections.spawn(serve(engine, stream, shared.clone(), logger));
+                    }
+                },
+                Err(stream_err) => error!(logger, "ERROR connecting to stream: {}", stream_err),
+            }
+        }
+        drop(connection);
+
+        info!(self.logger, "shutting down, draining connections...");
+        let drain = async { while connections.join_next().await.is_some() {} };
+        if tokio::time::timeout(self.config.drain_timeout, drain)
+            .await
+            .is_err()
+        {
+            warn!(self.logger, "drain timed out"; "abandoned" => connections.len());
+            connections.abort_all();
+        }
+        self.engine.run(|engine| engine.flush()).await?;
+        info!(self.logger, "shutdown complete");
+        Ok(())
+    }
+}
+
+async fn serve<E: KvsEngine>(
+    engine: AsyncKvsEngine<E>,
+    mut stream: TcpStream,
+    shared: Arc<Shared>,
+    logger: slog::Logger,
+) {
+    let metrics = &shared.metrics;
+    let peer = match stream.peer_addr() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => "unknown".to_owned(),
+    };
+    let logger = logger.new(o!("peer" => peer.clone()));
+    info!(logger, "accepting incoming connection...");
+
+    loop {
+        // Picks up reloaded settings at each request
+        let live = shared.reload.current();
+        let limits = &live.config.limits;
+        let request = match read_request(&mut stream, limits).await {
+            Ok(Some((request, len))) => {
+                metrics.add_bytes_read(len);
+                request
+            }
+            Ok(None) => break,
+            Err(Error::Rejected(reason)) => {
+                warn!(logger, "rejected request"; "reason" => reason.as_str());
+                let response = Response::Rejected(reason);
+                let _ = with_timeout(
+                    limits.write_timeout,
+                    frame::write_frame_async(&mut stream, &response),
+                )
+                .await;
+                break;
+            }
+            Err(e) => {
+                error!(logger, "ERROR deserializing request: {}", e);
+                break;
+            }
+        };
+        let started = Instant::now();
+        let kind = request.kind();
+        let request_id = shared.request_ids.fetch_add(1, Ordering::Relaxed) + 1;
+        let logger = logger.new(o!("request_id" => request_id));
+        let refused = limits.check(&request).or_else(|| {
+            let rate_limiter = live.rate_limiter.as_ref()?;
+            rate_limiter
+                .acquire(Client::Ip(&peer))
+                .err()
+                .map(overloaded)
+        });
+        let response = match refused {
+            Some(refusal) => refusal,
+            None => {
+                let handler_logger = logger.clone();
+                let log_data = shared.log_data;
+                let response = engine
+                    .run(move |engine| {
+                        Ok(handle_request(&engine, request, log_data, &handler_logger))
+                    })
+                    .await;
+                match response {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error!(logger, "ERROR handling request: {}", e);
+                        break;
+                    }
+                }
+            }
+        };
+        metrics.record(kind, &response, started.elapsed());
+        let written = with_timeout(
+            limits.write_timeout,
+            frame::write_frame_async(&mut stream, &response),
+        )
+        .await;
+        match written {
+            Ok(written) => metrics.add_bytes_written(written),
+            Err(e) => {
+                error!(logger, "ERROR serialzing response: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Reads one request and its size in bytes, allowing `idle_timeout` for it
+/// to start and `read_timeout` for the rest
+async fn read_request(stream: &mut TcpStream, limits: &Limits) -> Result<Option<(Request, u64)>> {
+    let len = match with_timeout(limits.idle_timeout, frame::read_header_async(stream)).await? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+    limits.check_frame(len)?;
+    let request = with_timeout(limits.read_timeout, frame::read_body_async(stream, len)).await?;
+    Ok(Some((request, frame::HEADER_LEN + u64::from(len))))
+}
+
+/// Reads the client's request, then tells it the server is full
+async fn reject(mut stream: TcpStream, limits: Limits) {
+    let limits = Limits {
+        idle_timeout: Some(REJECT_TIMEOUT),
+        read_timeout: Some(REJECT_TIMEOUT),
+        ..limits
+    };
+    if read_request(&mut stream, &limits).await.is_ok() {
+        let response = Response::Rejected("too many connections".to_owned());
+        let _ = with_timeout(
+            Some(REJECT_TIMEOUT),
+            frame::write_frame_async(&mut stream, &response),
+        )
+        .await;
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    operation: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, operation)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?,
+        None => operation.await,
+    }
+}
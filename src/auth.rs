@@ -0,0 +1,190 @@
+//! Authentication and per-key-prefix access control for `KvsServer`.
+//!
+//! Principals and their grants are loaded from a JSON file such as:
+//!
+//! ```json
+//! {
+//!   "principals": [
+//!     { "name": "admin", "password": "hunter2", "grants": [{ "prefix": "", "access": "admin" }] },
+//!     { "name": "reporting", "token": "s3cr3t", "grants": [{ "prefix": "reports/", "access": "read" }] }
+//!   ]
+//! }
+//! ```
+
+use crate::command::{Request, Response};
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// What a grant allows on its prefix. Each level includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// What a client presents in a `Request::Auth`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Credentials {
+    Token(String),
+    Password { user: String, password: String },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Grant {
+    pub prefix: String,
+    pub access: Access,
+}
+
+/// A user or service and the ways it may authenticate
+#[derive(Clone, Debug, Deserialize)]
+pub struct Principal {
+    pub name: String,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub grants: Vec<Grant>,
+}
+
+impl Principal {
+    /// Whether any grant covering `key` gives at least `access`
+    pub fn allows(&self, key: &str, access: Access) -> bool {
+        self.grants
+            .iter()
+            .any(|grant| key.starts_with(&grant.prefix) && grant.access >= access)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Acl {
+    pub principals: Vec<Principal>,
+}
+
+impl Acl {
+    pub fn load(path: &Path) -> Result<Acl> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn authenticate(&self, credentials: &Credentials) -> Option<&Principal> {
+        self.principals.iter().find(|principal| match credentials {
+            Credentials::Token(token) => principal
+                .token
+                .as_ref()
+                .is_some_and(|expected| constant_time_eq(expected, token)),
+            Credentials::Password { user, password } => {
+                principal.name == *user
+                    && principal
+                        .password
+                        .as_ref()
+                        .is_some_and(|expected| constant_time_eq(expected, password))
+            }
+        })
+    }
+}
+
+/// The authentication state of one connection
+pub(crate) struct Session {
+    acl: Arc<Acl>,
+    principal: Option<Principal>,
+}
+
+impl Session {
+    pub fn new(acl: Arc<Acl>) -> Session {
+        Session {
+            acl,
+            principal: None,
+        }
+    }
+
+    /// Swaps in a reloaded ACL. The connection stays authenticated as the same
+    /// principal, now with its new grants, unless it was removed or its
+    /// credentials changed, in which case it has to authenticate again.
+    pub fn reload(&mut self, acl: Arc<Acl>) {
+        self.principal = self.principal.take().and_then(|old| {
+            acl.principals
+                .iter()
+                .find(|principal| {
+                    principal.name == old.name
+                        && principal.token == old.token
+                        && principal.password == old.password
+                })
+                .cloned()
+        });
+        self.acl = acl;
+    }
+
+    /// The principal the connection authenticated as, if any
+    pub fn principal(&self) -> Option<&str> {
+        self.principal
+            .as_ref()
+            .map(|principal| principal.name.as_str())
+    }
+
+    /// Handles `Request::Auth` and checks everything else against the ACL,
+    /// returning the response to send instead of running the request.
+    /// Multi-key requests are refused as a whole if any key is out of bounds.
+    pub fn check(&mut self, request: &Request) -> Option<Response> {
+        if let Request::Auth(credentials) = request {
+            self.principal = self.acl.authenticate(credentials).cloned();
+            return Some(match self.principal {
+                Some(_) => Response::OK("".to_owned()),
+                None => Response::Unauthorized("invalid credentials".to_owned()),
+            });
+        }
+        let principal = match &self.principal {
+            Some(principal) => principal,
+            None => return Some(Response::Unauthorized("authentication required".to_owned())),
+        };
+        // Admin requests act on the whole store, so need admin access to all of it
+        if let Request::Admin(_) = request {
+            if principal.allows("", Access::Admin) {
+                return None;
+            }
+            return Some(Response::Unauthorized(format!(
+                "{} lacks admin access",
+                principal.name
+            )));
+        }
+        let access = if request.is_read_only() {
+            Access::Read
+        } else {
+            Access::Write
+        };
+        request
+            .keys()
+            .into_iter()
+            .find(|(key, _)| !principal.allows(key, access))
+            .map(|(key, _)| {
+                Response::Unauthorized(format!(
+                    "{} lacks {} access to {}",
+                    principal.name, access, key
+                ))
+            })
+    }
+}
+
+// Compares secrets without returning early on the first differing byte
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
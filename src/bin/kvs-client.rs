@@ -1,6 +1,10 @@
-use kvs::{command::Request, KvsClient, Result};
-use std::net::SocketAddr;
+use kvs::auth::Credentials;
+use kvs::command::{Admin, Request, Response, Watch};
+use kvs::tls::ClientTlsConfig;
+use kvs::{Address, AsyncKvsClient, Error, KvsClient, Result};
+use std::path::PathBuf;
 use std::process::exit;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -11,44 +15,258 @@ enum ClientOpts {
         key: String,
         #[structopt(index = 2, required = true)]
         value: String,
-        #[structopt(default_value = "127.0.0.0:4000", long, help = "IP:PORT")]
-        addr: SocketAddr,
+        #[structopt(flatten)]
+        conn: ConnectOpts,
     },
     Get {
         #[structopt(required = true)]
         key: String,
-        #[structopt(default_value = "127.0.0.0:4000", long, help = "IP:PORT")]
-        addr: SocketAddr,
+        #[structopt(flatten)]
+        conn: ConnectOpts,
     },
     Rm {
         #[structopt(required = true)]
         key: String,
-        #[structopt(default_value = "127.0.0.0:4000", long, help = "IP:PORT")]
-        addr: SocketAddr,
+        #[structopt(flatten)]
+        conn: ConnectOpts,
+    },
+    /// Gets several keys in one request, printing one line per key
+    Mget {
+        #[structopt(required = true)]
+        keys: Vec<String>,
+        #[structopt(flatten)]
+        conn: ConnectOpts,
+    },
+    /// Sets several keys in one request
+    Mset {
+        #[structopt(required = true, value_names = &["KEY", "VALUE"])]
+        pairs: Vec<String>,
+        #[structopt(flatten)]
+        conn: ConnectOpts,
+    },
+    /// Prints a line for each later write to a key until interrupted
+    Watch {
+        #[structopt(required = true)]
+        key: String,
+        #[structopt(long, help = "Watch every key starting with KEY")]
+        prefix: bool,
+        #[structopt(flatten)]
+        conn: ConnectOpts,
+    },
+    /// Runs an operator command; needs admin access when the server has an ACL
+    Admin {
+        #[structopt(
+            parse(try_from_str = parse_admin),
+            possible_values = &["compact", "flush", "stats", "config", "connections", "shutdown"]
+        )]
+        command: Admin,
+        #[structopt(flatten)]
+        conn: ConnectOpts,
     },
 }
 
+#[derive(Debug, StructOpt)]
+struct ConnectOpts {
+    #[structopt(
+        default_value = "127.0.0.0:4000",
+        long,
+        number_of_values = 1,
+        help = "IP:PORT or unix:PATH; repeat to fail over to further servers"
+    )]
+    addr: Vec<Address>,
+    #[structopt(long = "async", help = "Send the request from a tokio runtime")]
+    async_runtime: bool,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Connect over TLS, trusting this PEM CA"
+    )]
+    tls_ca: Option<PathBuf>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires_all = &["tls-ca", "tls-key"],
+        help = "PEM client certificate, for servers that verify clients"
+    )]
+    tls_cert: Option<PathBuf>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "tls-cert",
+        help = "PEM private key for --tls-cert"
+    )]
+    tls_key: Option<PathBuf>,
+    #[structopt(
+        long,
+        requires = "tls-ca",
+        help = "Name to verify the server certificate against [default: the IP of --addr]"
+    )]
+    tls_server_name: Option<String>,
+    #[structopt(long, conflicts_with = "user", help = "Authenticate with this token")]
+    token: Option<String>,
+    #[structopt(long, requires = "password", help = "Authenticate as this user")]
+    user: Option<String>,
+    #[structopt(long, requires = "user", help = "Password for --user")]
+    password: Option<String>,
+    #[structopt(
+        long,
+        default_value = "3",
+        help = "Times to retry when the server is overloaded"
+    )]
+    overload_retries: u32,
+    #[structopt(
+        long,
+        default_value = "30",
+        help = "Seconds to wait on the server before giving up"
+    )]
+    timeout: u64,
+}
+
+impl ConnectOpts {
+    fn send(&self, request: Request) -> Result<Option<String>> {
+        if self.async_runtime {
+            if self.tls_ca.is_some() || self.credentials().is_some() {
+                return Err(Error::Unsupported(
+                    "--async does not speak TLS or authenticate".to_owned(),
+                ));
+            }
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            return runtime.block_on(AsyncKvsClient::send(request, self.addr[0].clone()));
+        }
+        self.client()?.request(request)
+    }
+
+    fn client(&self) -> Result<KvsClient> {
+        if self.async_runtime {
+            return Err(Error::Unsupported(
+                "--async only sends get, set and rm".to_owned(),
+            ));
+        }
+        let mut builder = KvsClient::builder(self.addr[0].clone())
+            .request_timeout(Some(Duration::from_secs(self.timeout)))
+            .overload_retries(self.overload_retries);
+        for addr in &self.addr[1..] {
+            builder = builder.failover(addr.clone());
+        }
+        if let Some(ca) = &self.tls_ca {
+            builder = builder.tls(ClientTlsConfig {
+                ca: ca.clone(),
+                cert: self.tls_cert.clone(),
+                key: self.tls_key.clone(),
+                server_name: self.tls_server_name.clone(),
+            });
+        }
+        if let Some(credentials) = self.credentials() {
+            builder = builder.credentials(credentials);
+        }
+        builder.build()
+    }
+
+    fn credentials(&self) -> Option<Credentials> {
+        match (&self.token, &self.user, &self.password) {
+            (Some(token), _, _) => Some(Credentials::Token(token.clone())),
+            (None, Some(user), Some(password)) => Some(Credentials::Password {
+                user: user.clone(),
+                password: password.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn parse_admin(command: &str) -> std::result::Result<Admin, String> {
+    match command {
+        "compact" => Ok(Admin::Compact),
+        "flush" => Ok(Admin::Flush),
+        "stats" => Ok(Admin::Stats),
+        "config" => Ok(Admin::Config),
+        "connections" => Ok(Admin::Connections),
+        "shutdown" => Ok(Admin::Shutdown),
+        _ => Err(format!("unknown admin command {}", command)),
+    }
+}
+
 fn main() -> Result<()> {
     match ClientOpts::from_args() {
-        ClientOpts::Get { key, addr } => {
+        ClientOpts::Get { key, conn } => {
             // Write request over the wire
-            if let Some(found) = KvsClient::send(Request::Get(key), addr)? {
+            if let Some(found) = conn.send(Request::Get(key))? {
                 println!("{}", found);
             } else {
                 println!("Key not found");
             }
             exit(0);
         }
-        ClientOpts::Set { key, value, addr } => {
-            KvsClient::send(Request::Set(key, value), addr)?;
+        ClientOpts::Set { key, value, conn } => {
+            conn.send(Request::Set(key, value))?;
             exit(0);
         }
-        ClientOpts::Rm { key, addr } => {
-            if let Err(e) = KvsClient::send(Request::Rm(key), addr) {
+        ClientOpts::Rm { key, conn } => {
+            if let Err(e) = conn.send(Request::Rm(key)) {
                 eprintln!("{}", e);
                 exit(1);
             }
             exit(0);
         }
+        ClientOpts::Mget { keys, conn } => {
+            for found in conn.client()?.multi_get(keys)? {
+                println!("{}", found.as_deref().unwrap_or("Key not found"));
+            }
+            exit(0);
+        }
+        ClientOpts::Mset { pairs, conn } => {
+            if pairs.len() % 2 != 0 {
+                eprintln!("mset takes KEY VALUE pairs");
+                exit(1);
+            }
+            let pairs = pairs
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect();
+            conn.client()?.multi_set(pairs)?;
+            exit(0);
+        }
+        ClientOpts::Watch { key, prefix, conn } => {
+            let watch = if prefix {
+                Watch::Prefix(key)
+            } else {
+                Watch::Key(key)
+            };
+            for event in conn.client()?.watch(watch)? {
+                let event = event?;
+                match event.value {
+                    Some(value) => println!("{} set {} {}", event.version, event.key, value),
+                    None => println!("{} rm {}", event.version, event.key),
+                }
+            }
+            exit(0);
+        }
+        ClientOpts::Admin { command, conn } => {
+            match conn.client()?.call(Request::Admin(command))? {
+                Response::OK(_) => {}
+                Response::Entries(entries) => {
+                    for (name, value) in entries {
+                        println!("{} {}", name, value);
+                    }
+                }
+                Response::Connections(connections) => {
+                    for connection in connections {
+                        println!(
+                            "{} {} {} {}s {} requests",
+                            connection.id,
+                            connection.peer,
+                            connection.principal.as_deref().unwrap_or("-"),
+                            connection.age_secs,
+                            connection.requests
+                        );
+                    }
+                }
+                Response::Error(e) => return Err(Error::Response(e)),
+                _ => return Err(Error::Unspecified),
+            }
+            exit(0);
+        }
     }
 }
@@ -1,97 +1,837 @@
-use kvs::engines::{KvStore, KvsEngine, SledKvsEngine};
-use kvs::server::KvsServer;
-use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
-use kvs::Result;
-use slog::{error, o, warn, Drain};
+use kvs::async_server::AsyncKvsServer;
+use kvs::auth::Acl;
+use kvs::engines::{KvStore, KvsEngine, Manifest, SledKvsEngine};
+use kvs::gateway::HttpGateway;
+use kvs::logging::{LevelSwitch, LogData, RotatingFile, SwitchableLevel};
+use kvs::memcached::MemcachedServer;
+use kvs::metrics::{Metrics, MetricsExporter};
+use kvs::rate_limit::RateLimit;
+use kvs::server::{KvsServer, Limits, ReloadHandle, ServerConfig};
+use kvs::thread_pool::{
+    NaiveThreadPool, OverflowPolicy, PoolConfig, RayonThreadPool, SharedQueueOptions,
+    SharedQueueThreadPool, ThreadPool, WorkStealingThreadPool,
+};
+use kvs::tls::ServerTlsConfig;
+use kvs::{Address, Error, Result, ShutdownHandle};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use slog::{error, info, o, warn, Drain, Level};
 use std::env;
 use std::fs;
+use std::io::{self, Write};
 use std::net::SocketAddr;
+use std::num::ParseIntError;
+use std::path::PathBuf;
 use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use structopt::StructOpt;
 
-const DEFAULT_ENGINE: &str = "kvs";
+const DEFAULT_ADDR: &str = "127.0.0.0:4000";
+const DEFAULT_POOL: &str = "shared";
 
-#[derive(Debug, StructOpt)]
+#[derive(Clone, Debug, StructOpt)]
 #[structopt(name = "kvs-server")]
 struct ServerOpts {
-    #[structopt(default_value = "127.0.0.0:4000", long, help = "IP:PORT")]
-    addr: SocketAddr,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Read settings from this TOML file; flags given here take precedence"
+    )]
+    config: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "IP:PORT [default: 127.0.0.0:4000, unless --unix is given]"
+    )]
+    addr: Option<SocketAddr>,
+    #[structopt(long, parse(from_os_str), help = "Listen on this Unix socket path")]
+    unix: Option<PathBuf>,
+    #[structopt(long, parse(try_from_str = parse_mode), help = "Unix socket permission bits, e.g. 660")]
+    unix_mode: Option<u32>,
     #[structopt(long, help = "ENGINE-NAME")]
     engine: Option<String>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Keep the store in this directory [default: the current directory]"
+    )]
+    data_dir: Option<PathBuf>,
+    #[structopt(long, help = "Also serve the memcached text protocol on IP:PORT")]
+    memcached_addr: Option<SocketAddr>,
+    #[structopt(long, help = "Also serve the HTTP/JSON gateway on IP:PORT")]
+    http_addr: Option<SocketAddr>,
+    #[structopt(long, help = "Serve Prometheus metrics at http://IP:PORT/metrics")]
+    metrics_addr: Option<SocketAddr>,
+    #[structopt(long = "async", help = "Serve connections on a tokio runtime")]
+    async_runtime: bool,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "tls-key",
+        help = "Serve TLS with this PEM certificate chain"
+    )]
+    tls_cert: Option<PathBuf>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "tls-cert",
+        help = "PEM private key for --tls-cert"
+    )]
+    tls_key: Option<PathBuf>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "tls-cert",
+        help = "Require client certificates signed by this PEM CA"
+    )]
+    tls_ca: Option<PathBuf>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Require clients to authenticate against this JSON credentials and grants file"
+    )]
+    acl_file: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Run admin commands from any client when there is no --acl-file"
+    )]
+    allow_admin_without_acl: bool,
+    #[structopt(flatten)]
+    limits: LimitOpts,
+    #[structopt(flatten)]
+    log: LogOpts,
+    #[structopt(flatten)]
+    pool: PoolOpts,
+    // Only set from the config file
+    #[structopt(skip)]
+    storage: StorageConfig,
 }
 
-fn main() -> Result<()> {
-    let decorator = slog_term::PlainDecorator::new(std::io::stderr());
-    let drain = slog_term::CompactFormat::new(decorator).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
+/// The `--config` file. Each table mirrors a group of flags, e.g.
+///
+/// ```toml
+/// engine = "kvs"
+///
+/// [listen]
+/// addr = "127.0.0.1:4000"
+/// metrics_addr = "127.0.0.1:9000"
+///
+/// [pool]
+/// kind = "shared"
+/// threads = 8
+/// min_threads = 2
+///
+/// [storage]
+/// data_dir = "/var/lib/kvs"
+///
+/// [limits]
+/// max_connections = 512
+/// rate_limit = 100.0
+///
+/// [log]
+/// format = "json"
+/// level = "debug"
+///
+/// [auth]
+/// acl_file = "acl.json"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    engine: Option<String>,
+    listen: ListenConfig,
+    pool: PoolOpts,
+    storage: StorageConfig,
+    tls: TlsConfig,
+    limits: LimitOpts,
+    log: LogOpts,
+    auth: AuthConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ListenConfig {
+    addr: Option<SocketAddr>,
+    unix: Option<PathBuf>,
+    /// Written as an octal literal, e.g. `0o660`
+    unix_mode: Option<u32>,
+    memcached_addr: Option<SocketAddr>,
+    http_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    #[serde(rename = "async")]
+    async_runtime: bool,
+}
+
+// The `[pool]` table of the config file calls --pool `kind`
+#[derive(Clone, Debug, Default, Deserialize, StructOpt)]
+#[serde(default, deny_unknown_fields)]
+struct PoolOpts {
+    #[structopt(
+        long,
+        possible_values = &["naive", "shared", "rayon", "stealing"],
+        help = "Thread pool serving connections [default: shared]"
+    )]
+    #[serde(rename = "kind")]
+    pool: Option<String>,
+    #[structopt(long, help = "Threads in the pool [default: the number of CPUs]")]
+    threads: Option<u32>,
+    #[structopt(
+        long,
+        help = "Connections the shared pool queues for a thread before --overflow applies [default: unbounded]"
+    )]
+    queue_size: Option<usize>,
+    #[structopt(
+        long,
+        possible_values = &["block", "reject", "drop-oldest"],
+        help = "What a full --queue-size does with another connection [default: reject]"
+    )]
+    #[serde(deserialize_with = "deserialize_overflow")]
+    overflow: Option<OverflowPolicy>,
+    #[structopt(
+        long,
+        help = "Threads the shared pool keeps when idle, growing to --threads under load [default: --threads]"
+    )]
+    min_threads: Option<u32>,
+    #[structopt(
+        long,
+        help = "Seconds a shared pool thread above --min-threads waits for work before exiting [default: 60]"
+    )]
+    #[serde(rename = "idle_timeout")]
+    thread_idle_timeout: Option<u64>,
+}
 
-    let log = slog::Logger::root(drain, o!("version" => env!("CARGO_PKG_VERSION")));
-    let mut opts = ServerOpts::from_args();
-    let logger = log.new(o!("addr" => opts.addr.to_string(), "engine" => opts.engine.to_owned()));
+impl PoolOpts {
+    fn threads(&self) -> u32 {
+        self.threads.unwrap_or_else(|| {
+            thread::available_parallelism().map_or(1, |threads| threads.get() as u32)
+        })
+    }
 
-    let res = current_engine(&logger).and_then(|e| {
-        if opts.engine.is_none() {
-            opts.engine = e.to_owned();
+    /// Fills in whatever was not given on the command line from the config file
+    fn or(self, file: PoolOpts) -> PoolOpts {
+        PoolOpts {
+            pool: self.pool.or(file.pool),
+            threads: self.threads.or(file.threads),
+            queue_size: self.queue_size.or(file.queue_size),
+            overflow: self.overflow.or(file.overflow),
+            min_threads: self.min_threads.or(file.min_threads),
+            thread_idle_timeout: self.thread_idle_timeout.or(file.thread_idle_timeout),
         }
-        if e.is_some() && opts.engine != e {
-            error!(&logger, "Invalid engine!");
-            process::exit(1);
+    }
+
+    /// How to size the shared pool, given its `threads` at most
+    fn shared_options(&self, threads: u32) -> Result<SharedQueueOptions> {
+        let defaults = SharedQueueOptions::fixed(threads);
+        let min_threads = self.min_threads.unwrap_or(threads);
+        if min_threads > threads {
+            return Err(Error::Config(format!(
+                "--min-threads {} is more than --threads {}",
+                min_threads, threads
+            )));
         }
+        if self.queue_size == Some(0) {
+            return Err(Error::Config("--queue-size must be at least 1".to_owned()));
+        }
+        Ok(SharedQueueOptions {
+            min_threads,
+            max_threads: threads,
+            idle_timeout: self
+                .thread_idle_timeout
+                .map_or(defaults.idle_timeout, Duration::from_secs),
+            queue_capacity: self.queue_size,
+            overflow: self.overflow.unwrap_or(OverflowPolicy::Reject),
+        })
+    }
 
-        run(opts, &logger)
-    });
+    /// Whether any option only the shared pool understands was given
+    fn shared_only(&self) -> bool {
+        self.queue_size.is_some()
+            || self.min_threads.is_some()
+            || self.thread_idle_timeout.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StorageConfig {
+    data_dir: Option<PathBuf>,
+    /// When a kvs store compacts: once the size of its active log, summed
+    /// over every set since the last compaction, passes this many bytes
+    compaction_threshold: Option<u64>,
+    /// Bytes of sled's page cache
+    cache_capacity: Option<u64>,
+    /// How often sled flushes to disk in the background, 0 to never
+    flush_every_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsConfig {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthConfig {
+    acl_file: Option<PathBuf>,
+    allow_admin_without_acl: bool,
+}
+
+// Overrides for `Limits`; anything not given keeps its default
+#[derive(Clone, Debug, Default, Deserialize, StructOpt)]
+#[serde(default, deny_unknown_fields)]
+struct LimitOpts {
+    #[structopt(
+        long,
+        help = "Seconds a started request may take to arrive, 0 to wait forever"
+    )]
+    read_timeout: Option<u64>,
+    #[structopt(long, help = "Seconds a response may take to send, 0 to wait forever")]
+    write_timeout: Option<u64>,
+    #[structopt(
+        long,
+        help = "Seconds a connection may idle between requests, 0 to wait forever"
+    )]
+    idle_timeout: Option<u64>,
+    #[structopt(long, help = "Largest request accepted, in bytes")]
+    max_frame_size: Option<u32>,
+    #[structopt(long, help = "Largest key accepted, in bytes")]
+    max_key_size: Option<usize>,
+    #[structopt(long, help = "Largest value accepted, in bytes")]
+    max_value_size: Option<usize>,
+    #[structopt(long, help = "Connections served at once before new ones are rejected")]
+    max_connections: Option<usize>,
+    #[structopt(
+        long,
+        help = "Connections waiting for a thread before new ones are shed"
+    )]
+    max_backlog: Option<usize>,
+    #[structopt(long, parse(try_from_str = parse_rate), help = "Requests per second allowed per client")]
+    rate_limit: Option<f64>,
+    #[structopt(
+        long,
+        requires = "rate-limit",
+        help = "Requests a client may burst above --rate-limit [default: one second's worth]"
+    )]
+    rate_burst: Option<u32>,
+}
+
+// Logging flags, which are the `[log]` table of the config file without their `log_` prefix
+#[derive(Clone, Debug, Default, Deserialize, StructOpt)]
+#[serde(default, deny_unknown_fields)]
+struct LogOpts {
+    #[structopt(
+        long,
+        possible_values = &["text", "json"],
+        help = "Write logs as text or one JSON object per line [default: text]"
+    )]
+    #[serde(rename = "format")]
+    log_format: Option<String>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_level),
+        help = "Least severe level logged: trace, debug, info, warning, error or critical [default: info]"
+    )]
+    #[serde(rename = "level", deserialize_with = "deserialize_level")]
+    log_level: Option<Level>,
+    #[structopt(long, parse(from_os_str), help = "Log to this file instead of stderr")]
+    #[serde(rename = "file")]
+    log_file: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Bytes --log-file may grow to before it is rotated [default: 10485760]"
+    )]
+    #[serde(rename = "max_size")]
+    log_max_size: Option<u64>,
+    #[structopt(long, help = "Rotated copies of --log-file to keep [default: 5]")]
+    #[serde(rename = "keep")]
+    log_keep: Option<u32>,
+    #[structopt(
+        long,
+        possible_values = &["redacted", "keys", "all"],
+        help = "How much of each request's keys and values to log [default: redacted]"
+    )]
+    #[serde(rename = "data", deserialize_with = "deserialize_log_data")]
+    log_data: Option<LogData>,
+}
+
+impl LogOpts {
+    /// Builds the root logger, along with a switch for its level. Buffered
+    /// records are written out when the guard drops.
+    fn logger(&self) -> Result<(slog::Logger, LevelSwitch, slog_async::AsyncGuard)> {
+        let writer: Box<dyn Write + Send> = match &self.log_file {
+            Some(path) => Box::new(RotatingFile::open(
+                path,
+                self.log_max_size.unwrap_or(10 * 1024 * 1024),
+                self.log_keep.unwrap_or(5),
+            )?),
+            None => Box::new(io::stderr()),
+        };
+        let (drain, guard) = match self.log_format.as_deref().unwrap_or("text") {
+            "json" => {
+                let drain = slog_json::Json::new(writer)
+                    .set_flush(true)
+                    .add_default_keys()
+                    .build();
+                slog_async::Async::new(drain.fuse()).build_with_guard()
+            }
+            "text" => {
+                let decorator = slog_term::PlainDecorator::new(writer);
+                let drain = slog_term::CompactFormat::new(decorator).build();
+                slog_async::Async::new(drain.fuse()).build_with_guard()
+            }
+            format => {
+                return Err(Error::Config(format!(
+                    "{} is not a log format, use text or json",
+                    format
+                )))
+            }
+        };
+        let drain = SwitchableLevel::new(drain, self.level());
+        let switch = drain.switch();
+        let logger = slog::Logger::root(drain.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        Ok((logger, switch, guard))
+    }
 
-    if let Err(e) = res {
+    fn level(&self) -> Level {
+        self.log_level.unwrap_or(Level::Info)
+    }
+
+    /// Fills in whatever was not given on the command line from the config file
+    fn or(self, file: LogOpts) -> LogOpts {
+        LogOpts {
+            log_format: self.log_format.or(file.log_format),
+            log_level: self.log_level.or(file.log_level),
+            log_file: self.log_file.or(file.log_file),
+            log_max_size: self.log_max_size.or(file.log_max_size),
+            log_keep: self.log_keep.or(file.log_keep),
+            log_data: self.log_data.or(file.log_data),
+        }
+    }
+}
+
+impl LimitOpts {
+    /// Fills in whatever was not given on the command line from the config file
+    fn or(self, file: LimitOpts) -> LimitOpts {
+        LimitOpts {
+            read_timeout: self.read_timeout.or(file.read_timeout),
+            write_timeout: self.write_timeout.or(file.write_timeout),
+            idle_timeout: self.idle_timeout.or(file.idle_timeout),
+            max_frame_size: self.max_frame_size.or(file.max_frame_size),
+            max_key_size: self.max_key_size.or(file.max_key_size),
+            max_value_size: self.max_value_size.or(file.max_value_size),
+            max_connections: self.max_connections.or(file.max_connections),
+            max_backlog: self.max_backlog.or(file.max_backlog),
+            rate_limit: self.rate_limit.or(file.rate_limit),
+            rate_burst: self.rate_burst.or(file.rate_burst),
+        }
+    }
+
+    fn limits(&self) -> Limits {
+        let timeout = |secs: u64| Some(secs).filter(|&s| s > 0).map(Duration::from_secs);
+        let defaults = Limits::default();
+        Limits {
+            read_timeout: self.read_timeout.map_or(defaults.read_timeout, timeout),
+            write_timeout: self.write_timeout.map_or(defaults.write_timeout, timeout),
+            idle_timeout: self.idle_timeout.map_or(defaults.idle_timeout, timeout),
+            max_frame_size: self.max_frame_size.unwrap_or(defaults.max_frame_size),
+            max_key_size: self.max_key_size.unwrap_or(defaults.max_key_size),
+            max_value_size: self.max_value_size.unwrap_or(defaults.max_value_size),
+            max_connections: self.max_connections.or(defaults.max_connections),
+            max_backlog: self.max_backlog.or(defaults.max_backlog),
+        }
+    }
+
+    fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit.map(|per_second| RateLimit {
+            per_second,
+            burst: self
+                .rate_burst
+                .unwrap_or_else(|| per_second.ceil().max(1.0) as u32),
+        })
+    }
+}
+
+impl ServerOpts {
+    /// Fills in the settings not given on the command line from `--config`, if any
+    fn with_config_file(self) -> Result<ServerOpts> {
+        let path = match &self.config {
+            Some(path) => path,
+            None => return Ok(self),
+        };
+        let file: ConfigFile = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        if let Some(rate) = file.limits.rate_limit.filter(|&rate| !valid_rate(rate)) {
+            return Err(Error::Config(format!("{} is not a positive number", rate)));
+        }
+        Ok(ServerOpts {
+            addr: self.addr.or(file.listen.addr),
+            unix: self.unix.or(file.listen.unix),
+            unix_mode: self.unix_mode.or(file.listen.unix_mode),
+            engine: self.engine.or(file.engine),
+            data_dir: self.data_dir.or(file.storage.data_dir.clone()),
+            memcached_addr: self.memcached_addr.or(file.listen.memcached_addr),
+            http_addr: self.http_addr.or(file.listen.http_addr),
+            metrics_addr: self.metrics_addr.or(file.listen.metrics_addr),
+            async_runtime: self.async_runtime || file.listen.async_runtime,
+            tls_cert: self.tls_cert.or(file.tls.cert),
+            tls_key: self.tls_key.or(file.tls.key),
+            tls_ca: self.tls_ca.or(file.tls.ca),
+            acl_file: self.acl_file.or(file.auth.acl_file),
+            allow_admin_without_acl: self.allow_admin_without_acl
+                || file.auth.allow_admin_without_acl,
+            limits: self.limits.or(file.limits),
+            log: self.log.or(file.log),
+            pool: self.pool.or(file.pool),
+            storage: file.storage,
+            config: self.config,
+        })
+    }
+
+    /// The settings `KvsServer` takes, loading the ACL file if there is one
+    fn server_config(&self) -> Result<ServerConfig> {
+        // Neither gateway authenticates its clients, so either would let
+        // anyone around the ACL
+        if self.acl_file.is_some() && (self.memcached_addr.is_some() || self.http_addr.is_some()) {
+            return Err(Error::Config(
+                "--acl-file cannot be combined with --memcached-addr or --http-addr, \
+                 which do not authenticate clients"
+                    .to_owned(),
+            ));
+        }
+        Ok(ServerConfig {
+            unix_mode: self.unix_mode,
+            tls: self.tls(),
+            acl: self.acl_file.as_deref().map(Acl::load).transpose()?,
+            allow_admin_without_acl: self.allow_admin_without_acl,
+            limits: self.limits.limits(),
+            rate_limit: self.limits.rate_limit(),
+            log_data: self.log.log_data.unwrap_or_default(),
+            ..ServerConfig::default()
+        })
+    }
+
+    fn addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.addr.into_iter().map(Address::Tcp).collect();
+        addresses.extend(self.unix.clone().map(Address::Unix));
+        if addresses.is_empty() {
+            addresses.push(Address::Tcp(DEFAULT_ADDR.parse().unwrap()));
+        }
+        addresses
+    }
+
+    fn tls(&self) -> Option<ServerTlsConfig> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some(ServerTlsConfig {
+                cert: cert.clone(),
+                key: key.clone(),
+                client_ca: self.tls_ca.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn parse_rate(rate: &str) -> std::result::Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if valid_rate(rate) => Ok(rate),
+        _ => Err(format!("{} is not a positive number", rate)),
+    }
+}
+
+fn valid_rate(rate: f64) -> bool {
+    rate > 0.0 && rate.is_finite()
+}
+
+fn parse_level(level: &str) -> std::result::Result<Level, String> {
+    level
+        .parse()
+        .map_err(|_| format!("{} is not a log level", level))
+}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Level>, D::Error> {
+    let level = String::deserialize(deserializer)?;
+    parse_level(&level).map(Some).map_err(de::Error::custom)
+}
+
+fn deserialize_overflow<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<OverflowPolicy>, D::Error> {
+    let overflow = String::deserialize(deserializer)?;
+    overflow.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn deserialize_log_data<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<LogData>, D::Error> {
+    let data = String::deserialize(deserializer)?;
+    data.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn parse_mode(mode: &str) -> std::result::Result<u32, ParseIntError> {
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+}
+
+fn main() -> Result<()> {
+    let cli = ServerOpts::from_args();
+    let opts = cli.clone().with_config_file()?;
+    // The guard flushes buffered records when dropped, after a graceful shutdown or an error
+    let (log, level, guard) = opts.log.logger()?;
+    let addresses: Vec<String> = opts.addresses().iter().map(Address::to_string).collect();
+    let logger = log.new(o!("addr" => addresses.join(","), "engine" => opts.engine.to_owned()));
+
+    if let Err(e) = run(opts, Reloader { cli, level }, &logger) {
         error!(logger, "{}", e);
+        drop(guard);
         process::exit(1);
     }
 
     Ok(())
 }
 
-fn run(opts: ServerOpts, logger: &slog::Logger) -> Result<()> {
-    let engine = opts.engine.unwrap_or_else(|| DEFAULT_ENGINE.to_owned());
-    fs::write(env::current_dir()?.join("engine"), engine.to_string())?;
+/// Reapplies the settings that are safe to change while running
+struct Reloader {
+    /// The flags the server was started with, which keep precedence over the config file
+    cli: ServerOpts,
+    level: LevelSwitch,
+}
+
+impl Reloader {
+    /// Rereads the config and ACL files, then swaps in the new log level,
+    /// limits and grants. Everything else needs a restart to change.
+    fn reload(&self, handle: &ReloadHandle) -> Result<()> {
+        let opts = self.cli.clone().with_config_file()?;
+        handle.reload(&opts.server_config()?)?;
+        self.level.set(opts.log.level());
+        Ok(())
+    }
+}
+
+fn run(opts: ServerOpts, reloader: Reloader, logger: &slog::Logger) -> Result<()> {
+    let dir = match &opts.data_dir {
+        Some(dir) => dir.clone(),
+        None => env::current_dir()?,
+    };
+    let manifest = Manifest::open(&dir, opts.engine.as_deref())?;
+    let logger = logger.new(o!("store_id" => manifest.store_id.clone()));
+    info!(logger, "opened data directory";
+        "data_dir" => dir.display().to_string(),
+        "format_version" => manifest.format_version);
+
+    let storage = &opts.storage;
+    match manifest.engine.as_str() {
+        "kvs" => {
+            let store = match storage.compaction_threshold {
+                Some(threshold) => KvStore::open_with_threshold(dir, threshold)?,
+                None => KvStore::open(dir)?,
+            };
+            run_on_pool(store, logger.new(o!("kvs" => "new kvs")), opts, reloader)
+        }
+        "sled" => {
+            let mut config = sled::Config::new().path(dir);
+            if let Some(capacity) = storage.cache_capacity {
+                config = config.cache_capacity(capacity);
+            }
+            if let Some(ms) = storage.flush_every_ms {
+                config = config.flush_every_ms(Some(ms).filter(|&ms| ms > 0));
+            }
+            run_on_pool(
+                SledKvsEngine::new(config.open()?),
+                logger.new(o!("sled" => "new sled")),
+                opts,
+                reloader,
+            )
+        }
+        _ => Err(Error::InvalidEngine),
+    }
+}
 
-    match engine.as_str() {
-        "kvs" => run_with(
-            KvStore::open(env::current_dir()?)?,
-            SharedQueueThreadPool::new(4)?,
-            logger.new(o!("kvs" => "new kvs")),
-            opts.addr,
+fn run_on_pool<E: KvsEngine>(
+    engine: E,
+    logger: slog::Logger,
+    opts: ServerOpts,
+    reloader: Reloader,
+) -> Result<()> {
+    let threads = opts.pool.threads();
+    if threads == 0 {
+        return Err(Error::Config(
+            "the pool needs at least one thread".to_owned(),
+        ));
+    }
+    let pool = opts
+        .pool
+        .pool
+        .clone()
+        .unwrap_or_else(|| DEFAULT_POOL.to_owned());
+    let logger = logger.new(o!("pool" => pool.clone(), "threads" => threads));
+    if opts.pool.shared_only() && pool != "shared" {
+        return Err(Error::Config(
+            "--queue-size, --min-threads and --thread-idle-timeout need the shared pool".to_owned(),
+        ));
+    }
+    let shared_options = opts.pool.shared_options(threads);
+    let config = PoolConfig {
+        logger: logger.clone(),
+        ..PoolConfig::default()
+    };
+    match pool.as_str() {
+        "naive" => run_with(
+            engine,
+            || NaiveThreadPool::with_config(threads, config),
+            logger,
+            opts,
+            reloader,
+        ),
+        "shared" => run_with(
+            engine,
+            || SharedQueueThreadPool::with_options(shared_options?, config),
+            logger,
+            opts,
+            reloader,
         ),
-        "sled" => run_with(
-            SledKvsEngine::new(sled::open(env::current_dir()?)?),
-            SharedQueueThreadPool::new(4)?,
-            logger.new(o!("sled" => "new sled")),
-            opts.addr,
+        "rayon" => run_with(
+            engine,
+            || RayonThreadPool::with_config(threads, config),
+            logger,
+            opts,
+            reloader,
         ),
-        _ => unreachable!(),
+        "stealing" => run_with(
+            engine,
+            || WorkStealingThreadPool::with_config(threads, config),
+            logger,
+            opts,
+            reloader,
+        ),
+        pool => Err(Error::Config(format!(
+            "{} is not a thread pool, use naive, shared, rayon or stealing",
+            pool
+        ))),
     }
 }
 
 fn run_with<E: KvsEngine, P: ThreadPool>(
     engine: E,
-    thread_pool: P,
+    thread_pool: impl FnOnce() -> Result<P>,
     logger: slog::Logger,
-    addr: SocketAddr,
+    opts: ServerOpts,
+    reloader: Reloader,
 ) -> Result<()> {
-    let mut server = KvsServer::new(engine, thread_pool, logger);
-    server.start(addr)
+    let config = opts.server_config()?;
+
+    if let Some(memcached_addr) = opts.memcached_addr {
+        // memcached clients hold their connections open, so give each one its own thread
+        let mut memcached = MemcachedServer::with_limits(
+            engine.clone(),
+            NaiveThreadPool::new(0)?,
+            logger.new(o!("memcached_addr" => memcached_addr.to_string())),
+            opts.limits.limits(),
+        );
+        let logger = logger.clone();
+        thread::spawn(move || {
+            if let Err(e) = memcached.start(memcached_addr) {
+                error!(logger, "memcached listener stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(http_addr) = opts.http_addr {
+        let mut gateway = HttpGateway::with_limits(
+            engine.clone(),
+            SharedQueueThreadPool::new(opts.pool.threads())?,
+            logger.new(o!("http_addr" => http_addr.to_string())),
+            opts.limits.limits(),
+        );
+        let logger = logger.clone();
+        thread::spawn(move || {
+            if let Err(e) = gateway.start(http_addr) {
+                error!(logger, "http gateway stopped: {}", e);
+            }
+        });
+    }
+
+    if opts.async_runtime {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let addr = match opts.addresses().as_slice() {
+            [Address::Tcp(addr)] => *addr,
+            _ => {
+                return Err(Error::Unsupported(
+                    "--async serves a single TCP address".to_owned(),
+                ))
+            }
+        };
+        let mut server = AsyncKvsServer::with_config(engine.clone(), logger.clone(), config);
+        export_metrics(opts.metrics_addr, engine, server.metrics(), &logger);
+        reload_on_signal(reloader, server.reload_handle(), logger.clone())?;
+        shutdown_on_signal(server.shutdown_handle(), logger)?;
+        return runtime.block_on(server.start(addr));
+    }
+
+    let thread_pool = thread_pool()?;
+    let mut server = KvsServer::with_config(engine.clone(), thread_pool, logger.clone(), config);
+    export_metrics(opts.metrics_addr, engine, server.metrics(), &logger);
+    reload_on_signal(reloader, server.reload_handle(), logger.clone())?;
+    shutdown_on_signal(server.shutdown_handle(), logger)?;
+    server.start_on(&opts.addresses())
 }
 
-fn current_engine(logger: &slog::Logger) -> Result<Option<String>> {
-    let engine_file = env::current_dir()?.join("engine");
-    if !engine_file.exists() {
-        return Ok(None);
+/// Serves the metrics on `addr`, if given, from a thread of its own
+fn export_metrics<E: KvsEngine>(
+    addr: Option<SocketAddr>,
+    engine: E,
+    metrics: Arc<Metrics>,
+    logger: &slog::Logger,
+) {
+    if let Some(addr) = addr {
+        let logger = logger.new(o!("metrics_addr" => addr.to_string()));
+        let mut exporter = MetricsExporter::new(engine, metrics, logger.clone());
+        thread::spawn(move || {
+            if let Err(e) = exporter.start(addr) {
+                error!(logger, "metrics exporter stopped: {}", e);
+            }
+        });
     }
+}
 
-    match fs::read_to_string(engine_file)?.as_str() {
-        "kvs" => Ok(Some("kvs".to_owned())),
-        "sled" => Ok(Some("sled".to_owned())),
-        e => {
-            warn!(logger, "Invalid engine: {}", e);
-            Ok(None)
+/// Reloads settings on SIGHUP, keeping the old ones if the new ones are invalid
+fn reload_on_signal(reloader: Reloader, handle: ReloadHandle, logger: slog::Logger) -> Result<()> {
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            match reloader.reload(&handle) {
+                Ok(()) => info!(logger, "reloaded settings"),
+                Err(e) => error!(
+                    logger,
+                    "ERROR reloading settings, keeping the old ones: {}", e
+                ),
+            }
         }
-    }
+    });
+    Ok(())
+}
+
+/// Shuts the server down gracefully on SIGINT/SIGTERM; a second signal exits immediately
+fn shutdown_on_signal(handle: ShutdownHandle, logger: slog::Logger) -> Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        let mut signals = signals.forever();
+        if let Some(signal) = signals.next() {
+            info!(logger, "received signal, shutting down"; "signal" => signal);
+            handle.shutdown();
+        }
+        if signals.next().is_some() {
+            warn!(logger, "received second signal, exiting immediately");
+            process::exit(1);
+        }
+    });
+    Ok(())
 }
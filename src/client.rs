@@ -1,23 +1,464 @@
-use crate::command::{Request, Response};
+use crate::auth::Credentials;
+use crate::command::{Request, Response, Watch};
+use crate::engines::Event;
+use crate::error::Error;
+use crate::frame;
+use crate::tls::ClientTlsConfig;
+use crate::transport::{Address, Stream};
 use crate::Result;
-use std::net::{SocketAddr, TcpStream};
+use rustls::pki_types::ServerName;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct KvsClient {}
+// Backoff between retries doubles each time, up to this
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Configures a `KvsClient`
+pub struct KvsClientBuilder {
+    addresses: Vec<Address>,
+    tls: Option<ClientTlsConfig>,
+    credentials: Option<Credentials>,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    retries: u32,
+    retry_backoff: Duration,
+    overload_retries: u32,
+    max_idle_connections: usize,
+    idle_timeout: Duration,
+}
+
+impl KvsClientBuilder {
+    pub fn new<A: Into<Address>>(addr: A) -> KvsClientBuilder {
+        KvsClientBuilder {
+            addresses: vec![addr.into()],
+            tls: None,
+            credentials: None,
+            connect_timeout: Some(Duration::from_secs(5)),
+            request_timeout: Some(Duration::from_secs(30)),
+            retries: 2,
+            retry_backoff: Duration::from_millis(50),
+            overload_retries: 0,
+            max_idle_connections: 4,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Adds a server to fail over to when the ones before it are unreachable
+    pub fn failover<A: Into<Address>>(mut self, addr: A) -> KvsClientBuilder {
+        self.addresses.push(addr.into());
+        self
+    }
+
+    /// Speaks TLS to the servers, verifying them against `tls.ca`
+    pub fn tls(mut self, tls: ClientTlsConfig) -> KvsClientBuilder {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Authenticates every connection with `credentials` before sending requests
+    pub fn credentials(mut self, credentials: Credentials) -> KvsClientBuilder {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// How long to wait for a TCP connection. `None` waits on the OS.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> KvsClientBuilder {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long each read or write of a request may block. `None` waits forever.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> KvsClientBuilder {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Retries requests that fail on the network up to `retries` times, waiting
+    /// `backoff` and doubling it each time. Requests that may have reached the
+    /// server are only retried if repeating them is harmless.
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> KvsClientBuilder {
+        self.retries = retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Retries requests the server turns away as overloaded up to `retries`
+    /// times, waiting at least as long as the server asks, with exponential backoff
+    pub fn overload_retries(mut self, retries: u32) -> KvsClientBuilder {
+        self.overload_retries = retries;
+        self
+    }
+
+    /// Keeps up to `max` connections open between requests. Connections idle
+    /// longer than `idle_timeout` are closed rather than reused, so it should
+    /// be shorter than the server's own idle timeout.
+    pub fn pool(mut self, max: usize, idle_timeout: Duration) -> KvsClientBuilder {
+        self.max_idle_connections = max;
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn build(self) -> Result<KvsClient> {
+        let tls = match &self.tls {
+            Some(tls) => Some(tls.load()?),
+            None => None,
+        };
+        let servers = self
+            .addresses
+            .iter()
+            .cloned()
+            .map(|address| {
+                let server_name = match &self.tls {
+                    Some(tls) => Some(tls.server_name(&address)?),
+                    None => None,
+                };
+                Ok(Server {
+                    address,
+                    server_name,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(KvsClient {
+            servers,
+            active: AtomicUsize::new(0),
+            tls,
+            credentials: self.credentials,
+            connect_timeout: self.connect_timeout,
+            request_timeout: self.request_timeout,
+            retries: self.retries,
+            retry_backoff: self.retry_backoff,
+            overload_retries: self.overload_retries,
+            max_idle_connections: self.max_idle_connections,
+            idle_timeout: self.idle_timeout,
+            pool: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+struct Server {
+    address: Address,
+    server_name: Option<ServerName<'static>>,
+}
+
+struct Connection {
+    stream: Stream,
+    server: usize,
+    idle_since: Instant,
+}
+
+/// A thread-safe client that keeps a pool of open connections to the first
+/// reachable server of those it was built with
+pub struct KvsClient {
+    servers: Vec<Server>,
+    // The server new connections go to, moved along when one can't be reached
+    active: AtomicUsize,
+    tls: Option<Arc<rustls::ClientConfig>>,
+    credentials: Option<Credentials>,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    retries: u32,
+    retry_backoff: Duration,
+    overload_retries: u32,
+    max_idle_connections: usize,
+    idle_timeout: Duration,
+    pool: Mutex<Vec<Connection>>,
+}
 
 impl KvsClient {
-    pub fn send(request: Request, addr: SocketAddr) -> Result<Option<String>> {
-        let stream = TcpStream::connect(addr)?;
-        match bincode::serialize_into(&stream, &request) {
-            Ok(_) => match bincode::deserialize_from(stream)? {
-                Response::OK(v) => Ok(Some(v)),
-                Response::NotFound => match request {
-                    Request::Get(_) => Ok(None),
-                    Request::Rm(_) => Err(crate::error::Error::KeyNotFound),
-                    _ => unreachable!(),
-                },
-                Response::Error(v) => Err(crate::error::Error::Response(v)),
-            },
-            Err(e) => Err(e.into()),
+    /// A client for a single plaintext server, with default settings
+    pub fn new<A: Into<Address>>(addr: A) -> KvsClient {
+        KvsClientBuilder::new(addr)
+            .build()
+            .expect("a client without TLS always builds")
+    }
+
+    pub fn builder<A: Into<Address>>(addr: A) -> KvsClientBuilder {
+        KvsClientBuilder::new(addr)
+    }
+
+    pub fn request(&self, request: Request) -> Result<Option<String>> {
+        let response = self.call(request.clone())?;
+        interpret(&request, response)
+    }
+
+    /// Fetches several keys in one request, in order
+    pub fn multi_get(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        let request = Request::MultiGet(keys);
+        self.call_multi(&request)?
+            .into_iter()
+            .map(|response| interpret(&request, response))
+            .collect()
+    }
+
+    /// Sets several keys in one request, failing if any could not be set
+    pub fn multi_set(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        let request = Request::MultiSet(pairs);
+        for response in self.call_multi(&request)? {
+            interpret(&request, response)?;
+        }
+        Ok(())
+    }
+
+    /// Removes several keys in one request, returning whether each existed
+    pub fn multi_remove(&self, keys: Vec<String>) -> Result<Vec<bool>> {
+        let request = Request::MultiRemove(keys);
+        self.call_multi(&request)?
+            .into_iter()
+            .map(|response| match interpret(&request, response) {
+                Ok(_) => Ok(true),
+                Err(Error::KeyNotFound) => Ok(false),
+                Err(e) => Err(e),
+            })
+            .collect()
+    }
+
+    /// Sends `request` and returns the server's raw response. Responses
+    /// refusing the request as a whole come back as errors.
+    pub fn call(&self, request: Request) -> Result<Response> {
+        let mut retries = 0;
+        let mut backoff = self.retry_backoff;
+        let mut overload_retries = 0;
+        let mut overload_backoff = self.retry_backoff;
+        loop {
+            match self.call_once(&request) {
+                Err(Failure::Network { sent, .. })
+                    if retries < self.retries && (!sent || request.is_idempotent()) =>
+                {
+                    self.fail_over();
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    retries += 1;
+                }
+                Err(Failure::Network { error, .. }) => return Err(error),
+                Err(Failure::Refused(Error::Overloaded(retry_after)))
+                    if overload_retries < self.overload_retries =>
+                {
+                    thread::sleep(retry_after.max(overload_backoff).min(MAX_BACKOFF));
+                    overload_backoff = (overload_backoff * 2).min(MAX_BACKOFF);
+                    overload_retries += 1;
+                }
+                Err(Failure::Refused(error)) => return Err(error),
+                Ok(response) => return Ok(response),
+            }
         }
     }
+
+    /// Watches for writes on a connection of its own, which stays open until
+    /// the returned stream is dropped. Only the initial request is retried.
+    pub fn watch(&self, watch: Watch) -> Result<WatchStream> {
+        let request = Request::Watch(watch);
+        let mut retries = 0;
+        let mut backoff = self.retry_backoff;
+        loop {
+            let server = self.active.load(Ordering::SeqCst);
+            let attempt = self.connect(server).and_then(|mut connection| {
+                let response = exchange(&mut connection.stream, &request)?;
+                Ok((connection, response))
+            });
+            match attempt.map_err(|error| Failure::classify(error, true)) {
+                Err(Failure::Network { .. }) if retries < self.retries => {
+                    self.fail_over();
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    retries += 1;
+                }
+                Err(Failure::Network { error, .. }) | Err(Failure::Refused(error)) => {
+                    return Err(error)
+                }
+                Ok((connection, response)) => {
+                    interpret(&request, response)?;
+                    // Events may be far apart, so only the server's closing ends the wait
+                    connection.stream.set_read_timeout(None)?;
+                    return Ok(WatchStream {
+                        stream: connection.stream,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Sends a single request over a plaintext connection
+    pub fn send<A: Into<Address>>(request: Request, addr: A) -> Result<Option<String>> {
+        KvsClient::new(addr).request(request)
+    }
+
+    fn call_multi(&self, request: &Request) -> Result<Vec<Response>> {
+        match self.call(request.clone())? {
+            Response::Multi(responses) => Ok(responses),
+            _ => Err(Error::Unspecified),
+        }
+    }
+
+    fn call_once(&self, request: &Request) -> std::result::Result<Response, Failure> {
+        let (mut connection, pooled) = self.checkout()?;
+        let mut response = try_exchange(&mut connection.stream, request);
+        if let Err(Failure::Network { sent, .. }) = response {
+            // The server may have closed the connection while it sat in the
+            // pool, so that alone doesn't count against the server. The
+            // request is only sent again if that can't apply it twice.
+            if pooled && (!sent || request.is_idempotent()) {
+                connection = self
+                    .connect(connection.server)
+                    .map_err(|error| Failure::classify(error, false))?;
+                response = try_exchange(&mut connection.stream, request);
+            }
+        }
+        let response = response?;
+        self.checkin(connection);
+        refusal_to_error(response).map_err(Failure::Refused)
+    }
+
+    /// Takes a live connection from the pool, or opens a new one, returning
+    /// whether it came from the pool
+    fn checkout(&self) -> std::result::Result<(Connection, bool), Failure> {
+        let mut pool = self.pool.lock().unwrap();
+        while let Some(connection) = pool.pop() {
+            if connection.idle_since.elapsed() < self.idle_timeout {
+                return Ok((connection, true));
+            }
+        }
+        drop(pool);
+
+        let server = self.active.load(Ordering::SeqCst);
+        self.connect(server)
+            .map(|connection| (connection, false))
+            .map_err(|error| Failure::classify(error, false))
+    }
+
+    fn checkin(&self, mut connection: Connection) {
+        let mut pool = self.pool.lock().unwrap();
+        if connection.server == self.active.load(Ordering::SeqCst)
+            && pool.len() < self.max_idle_connections
+        {
+            connection.idle_since = Instant::now();
+            pool.push(connection);
+        }
+    }
+
+    fn connect(&self, server: usize) -> Result<Connection> {
+        let Server {
+            address,
+            server_name,
+        } = &self.servers[server];
+        let mut stream = Stream::connect_timeout(address, self.connect_timeout)?;
+        stream.set_read_timeout(self.request_timeout)?;
+        stream.set_write_timeout(self.request_timeout)?;
+        if let (Some(tls), Some(server_name)) = (&self.tls, server_name) {
+            stream = stream.into_tls_client(tls.clone(), server_name.clone())?;
+        }
+        if let Some(credentials) = &self.credentials {
+            let auth = Request::Auth(credentials.clone());
+            refusal_to_error(exchange(&mut stream, &auth)?)?;
+        }
+        Ok(Connection {
+            stream,
+            server,
+            idle_since: Instant::now(),
+        })
+    }
+
+    /// Moves new connections on to the next server and drops pooled ones to the old
+    fn fail_over(&self) {
+        if self.servers.len() > 1 {
+            let next = (self.active.load(Ordering::SeqCst) + 1) % self.servers.len();
+            self.active.store(next, Ordering::SeqCst);
+            self.pool.lock().unwrap().clear();
+        }
+    }
+}
+
+/// The writes to a watched key or prefix, in order. Ends when the server
+/// closes the connection, after an error if the server ended the watch
+/// because the client fell behind.
+pub struct WatchStream {
+    stream: Stream,
+}
+
+impl Iterator for WatchStream {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Result<Event>> {
+        match frame::read_frame(&mut self.stream, frame::DEFAULT_MAX_FRAME_SIZE) {
+            Ok(Some(Response::Event(event))) => Some(Ok(event)),
+            Ok(Some(Response::Error(e))) => Some(Err(Error::Response(e))),
+            Ok(Some(_)) => Some(Err(Error::Unspecified)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Why one attempt at a request failed
+enum Failure {
+    /// The connection failed; `sent` if the request may have reached the server
+    Network { error: Error, sent: bool },
+    /// The server answered, refusing the request, or the failure is not one
+    /// that retrying could fix
+    Refused(Error),
+}
+
+impl Failure {
+    fn classify(error: Error, sent: bool) -> Failure {
+        match &error {
+            // Garbled data, such as a failed TLS handshake, won't improve with retrying
+            Error::Io(e) if e.kind() != io::ErrorKind::InvalidData => {
+                Failure::Network { error, sent }
+            }
+            _ => Failure::Refused(error),
+        }
+    }
+}
+
+fn exchange(stream: &mut Stream, request: &Request) -> Result<Response> {
+    frame::write_frame(&mut *stream, request)?;
+    read_response(stream)
+}
+
+/// Like `exchange`, but tells whether the request may have reached the
+/// server: one that failed to be written whole didn't, as the server can't
+/// act on part of a frame
+fn try_exchange(stream: &mut Stream, request: &Request) -> std::result::Result<Response, Failure> {
+    frame::write_frame(&mut *stream, request).map_err(|error| Failure::classify(error, false))?;
+    read_response(stream).map_err(|error| Failure::classify(error, true))
+}
+
+fn read_response(stream: &mut Stream) -> Result<Response> {
+    match frame::read_frame(stream, frame::DEFAULT_MAX_FRAME_SIZE)? {
+        Some(response) => Ok(response),
+        None => Err(closed_by_server()),
+    }
+}
+
+pub(crate) fn closed_by_server() -> Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by server").into()
+}
+
+/// Turns responses refusing a request outright into errors, passing the rest through
+pub(crate) fn refusal_to_error(response: Response) -> Result<Response> {
+    match response {
+        Response::Unauthorized(v) => Err(Error::Unauthorized(v)),
+        Response::Rejected(v) => Err(Error::Rejected(v)),
+        Response::Overloaded { retry_after_ms } => {
+            Err(Error::Overloaded(Duration::from_millis(retry_after_ms)))
+        }
+        response => Ok(response),
+    }
+}
+
+/// Maps a server response onto the result of the request that produced it.
+/// For multi-key requests, `response` is the result for one of the keys.
+pub(crate) fn interpret(request: &Request, response: Response) -> Result<Option<String>> {
+    match refusal_to_error(response)? {
+        Response::OK(v) => Ok(Some(v)),
+        Response::NotFound => match request {
+            Request::Get(_) | Request::MultiGet(_) => Ok(None),
+            Request::Rm(_) | Request::MultiRemove(_) => Err(Error::KeyNotFound),
+            _ => unreachable!(),
+        },
+        Response::Error(v) => Err(Error::Response(v)),
+        _ => Err(Error::Unspecified),
+    }
 }
@@ -1,3 +1,5 @@
+use crate::auth::Credentials;
+use crate::engines::Event;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -6,11 +8,132 @@ pub enum Command {
     Rm(String),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Request {
     Get(String),
     Set(String, String),
     Rm(String),
+    MultiGet(Vec<String>),
+    MultiSet(Vec<(String, String)>),
+    MultiRemove(Vec<String>),
+    /// Authenticates the rest of the connection
+    Auth(Credentials),
+    /// Turns the connection into a stream of `Response::Event`s, one per later
+    /// write to the watched keys
+    Watch(Watch),
+    /// Operator commands, which need admin access to the whole keyspace
+    Admin(Admin),
+}
+
+/// The keys a `Request::Watch` follows
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Watch {
+    Key(String),
+    Prefix(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Admin {
+    /// Rewrites the store's files without their dead entries
+    Compact,
+    /// Makes every completed write durable
+    Flush,
+    /// Answered with `Response::Entries` of statistic names and values
+    Stats,
+    /// Answered with `Response::Entries` of setting names and values
+    Config,
+    /// Answered with `Response::Connections`
+    Connections,
+    /// Stops the server as a `ShutdownHandle` would
+    Shutdown,
+}
+
+/// A connection open on the server, as listed by `Admin::Connections`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub peer: String,
+    /// Who the connection authenticated as, if anyone
+    pub principal: Option<String>,
+    pub age_secs: u64,
+    pub requests: u64,
+}
+
+impl Watch {
+    pub(crate) fn prefix(&self) -> &str {
+        match self {
+            Watch::Key(key) | Watch::Prefix(key) => key,
+        }
+    }
+
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        match self {
+            Watch::Key(watched) => watched == key,
+            Watch::Prefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+impl Request {
+    /// The keys the request touches, each with the value it would write
+    pub(crate) fn keys(&self) -> Vec<(&str, Option<&str>)> {
+        match self {
+            Request::Get(key) | Request::Rm(key) => vec![(key, None)],
+            Request::Set(key, value) => vec![(key, Some(value))],
+            Request::MultiGet(keys) | Request::MultiRemove(keys) => {
+                keys.iter().map(|key| (key.as_str(), None)).collect()
+            }
+            Request::MultiSet(pairs) => pairs
+                .iter()
+                .map(|(key, value)| (key.as_str(), Some(value.as_str())))
+                .collect(),
+            Request::Auth(_) => vec![],
+            // Access to a prefix is checked as access to the prefix itself
+            Request::Watch(watch) => vec![(watch.prefix(), None)],
+            Request::Admin(_) => vec![],
+        }
+    }
+
+    /// A short name for the kind of request, for logs and metrics
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Request::Get(_) => "get",
+            Request::Set(..) => "set",
+            Request::Rm(_) => "rm",
+            Request::MultiGet(_) => "mget",
+            Request::MultiSet(_) => "mset",
+            Request::MultiRemove(_) => "mrm",
+            Request::Auth(_) => "auth",
+            Request::Watch(_) => "watch",
+            Request::Admin(_) => "admin",
+        }
+    }
+
+    /// Whether sending the request twice has the same effect as sending it once
+    pub(crate) fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            Request::Get(_)
+                | Request::Set(..)
+                | Request::MultiGet(_)
+                | Request::MultiSet(_)
+                | Request::Auth(_)
+                | Request::Watch(_)
+                | Request::Admin(Admin::Stats | Admin::Config | Admin::Connections)
+        )
+    }
+
+    /// Whether the request leaves the store unchanged
+    pub(crate) fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Request::Get(_)
+                | Request::MultiGet(_)
+                | Request::Auth(_)
+                | Request::Watch(_)
+                | Request::Admin(Admin::Stats | Admin::Config | Admin::Connections)
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -18,4 +141,17 @@ pub enum Response {
     OK(String),
     Error(String),
     NotFound,
+    Entries(Vec<(String, String)>),
+    /// One response per key of a multi-key request, in request order
+    Multi(Vec<Response>),
+    Unauthorized(String),
+    /// The server refused the connection or request for exceeding a limit
+    Rejected(String),
+    /// The server is shedding load or the client is over its rate limit; try again later
+    Overloaded {
+        retry_after_ms: u64,
+    },
+    /// A write to a watched key, sent after a `Request::Watch` is acknowledged
+    Event(Event),
+    Connections(Vec<ConnectionInfo>),
 }
@@ -0,0 +1,92 @@
+use crate::command::ConnectionInfo;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// The connections a server is serving, for `Admin::Connections`
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionTable(Arc<Mutex<Table>>);
+
+#[derive(Default)]
+struct Table {
+    next_id: u64,
+    open: BTreeMap<u64, Entry>,
+}
+
+struct Entry {
+    peer: String,
+    principal: Option<String>,
+    opened: Instant,
+    requests: u64,
+}
+
+/// Keeps a connection listed until dropped
+pub(crate) struct ConnectionRecord {
+    table: ConnectionTable,
+    id: u64,
+}
+
+impl ConnectionTable {
+    pub fn open(&self, peer: String) -> ConnectionRecord {
+        let mut table = self.0.lock().unwrap();
+        table.next_id += 1;
+        let id = table.next_id;
+        table.open.insert(
+            id,
+            Entry {
+                peer,
+                principal: None,
+                opened: Instant::now(),
+                requests: 0,
+            },
+        );
+        ConnectionRecord {
+            table: self.clone(),
+            id,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().open.len()
+    }
+
+    /// Lists open connections, oldest first
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        self.0
+            .lock()
+            .unwrap()
+            .open
+            .iter()
+            .map(|(&id, entry)| ConnectionInfo {
+                id,
+                peer: entry.peer.clone(),
+                principal: entry.principal.clone(),
+                age_secs: entry.opened.elapsed().as_secs(),
+                requests: entry.requests,
+            })
+            .collect()
+    }
+}
+
+impl ConnectionRecord {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Counts a request, noting who the connection is authenticated as
+    pub fn served(&self, principal: Option<&str>) {
+        let mut table = self.table.0.lock().unwrap();
+        if let Some(entry) = table.open.get_mut(&self.id) {
+            entry.requests += 1;
+            if entry.principal.as_deref() != principal {
+                entry.principal = principal.map(str::to_owned);
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionRecord {
+    fn drop(&mut self) {
+        self.table.0.lock().unwrap().open.remove(&self.id);
+    }
+}
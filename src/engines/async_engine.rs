@@ -0,0 +1,47 @@
+use super::KvsEngine;
+use crate::Result;
+use std::future::Future;
+
+/// Adapts a blocking `KvsEngine` for use from async code.
+///
+/// Every call runs on tokio's blocking thread pool so that engine I/O and
+/// lock contention never stall the runtime's worker threads. The engine is
+/// cloned up front, so the returned futures are `Send` even though engines
+/// need not be `Sync`.
+#[derive(Clone)]
+pub struct AsyncKvsEngine<E: KvsEngine>(E);
+
+impl<E: KvsEngine> AsyncKvsEngine<E> {
+    pub fn new(engine: E) -> Self {
+        AsyncKvsEngine(engine)
+    }
+
+    /// Runs an arbitrary blocking operation against the engine
+    pub fn run<T, F>(&self, f: F) -> impl Future<Output = Result<T>> + Send
+    where
+        T: Send + 'static,
+        F: FnOnce(E) -> Result<T> + Send + 'static,
+    {
+        let engine = self.0.clone();
+        async move { tokio::task::spawn_blocking(move || f(engine)).await? }
+    }
+
+    pub fn get(&self, key: String) -> impl Future<Output = Result<Option<String>>> + Send {
+        self.run(move |engine| engine.get(key))
+    }
+
+    pub fn set(&self, key: String, value: String) -> impl Future<Output = Result<()>> + Send {
+        self.run(move |engine| engine.set(key, value))
+    }
+
+    pub fn remove(&self, key: String) -> impl Future<Output = Result<()>> + Send {
+        self.run(move |engine| engine.remove(key))
+    }
+
+    pub fn scan(
+        &self,
+        prefix: String,
+    ) -> impl Future<Output = Result<Vec<(String, String)>>> + Send {
+        self.run(move |engine| engine.scan(prefix))
+    }
+}
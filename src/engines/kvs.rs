@@ -1,4 +1,6 @@
 use crate::command::Command;
+use crate::engines::watch::{WatchHub, Watcher};
+use crate::engines::EngineStats;
 use crate::error::Error;
 use crate::{KvsEngine, Result};
 use std::collections::HashMap;
@@ -7,9 +9,10 @@ use std::io::prelude::*;
 use std::io::SeekFrom;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub const BUCKET_EXT: &str = "kvstore"; // {current_generation}.kvstore
-const COMPACTION_THRESHOLD: u64 = 1 * 1024 * 1024; // 1 MB
+const DEFAULT_COMPACTION_THRESHOLD: u64 = 1024 * 1024; // 1 MB
 
 #[derive(Clone)]
 pub struct KvStore(Arc<Mutex<KvStoreShared>>);
@@ -21,6 +24,13 @@ pub struct KvStoreShared {
     active_file: ActiveFile,
     current_gen: u64,
     uncompacted: u64,
+    // Kept up to date on every write so `stats` needn't look at the disk
+    generations: u64,
+    dead_bytes: u64,
+    compaction_threshold: u64,
+    compactions: u64,
+    compaction_time: Duration,
+    hub: WatchHub,
 }
 
 impl KvStoreShared {
@@ -30,22 +40,24 @@ impl KvStoreShared {
         // 2. Iterate through keydir and write all values to new file(s) with new offsets
         // 3. If step 2 succeeds, delete all marked files
         // 4. Create new active file
+        let started = Instant::now();
         let files_to_delete = get_sorted_files(self.dir.clone())?;
         let new_gen = self.current_gen + 2;
         let mut new_keydir = HashMap::new();
         let mut new_active_file = ActiveFile::new(self.dir.clone(), new_gen)?;
         for (key, _) in self.keydir.clone().iter() {
             if let Some(value) = self.get(key.to_owned())? {
-                let offset = new_active_file.fd.seek(SeekFrom::Current(0))?;
+                let offset = new_active_file.fd.stream_position()?;
+                serde_json::to_writer(&new_active_file.fd, &Command::Set(key.clone(), value))?;
+                writeln!(new_active_file.fd)?;
                 new_keydir.insert(
                     key.clone(),
                     KeyDirEntry {
                         file_id: new_active_file.path.clone(),
                         offset,
+                        len: new_active_file.fd.stream_position()? - offset,
                     },
                 );
-                serde_json::to_writer(&new_active_file.fd, &Command::Set(key.clone(), value))?;
-                writeln!(new_active_file.fd)?;
             }
         }
 
@@ -57,6 +69,10 @@ impl KvStoreShared {
             fs::remove_file(f.path())?;
         }
         self.uncompacted = 0;
+        self.generations = 1;
+        self.dead_bytes = 0;
+        self.compactions += 1;
+        self.compaction_time += started.elapsed();
 
         Ok(())
     }
@@ -84,19 +100,23 @@ impl KvStoreShared {
     fn set(&mut self, key: String, value: String) -> Result<()> {
         // writes should be write-through:
         // update the in-memory map + the file on disk at the same time (not atomic)
-        let offset = self.active_file.fd.seek(SeekFrom::Current(0))?;
-        self.keydir.insert(
-            key.clone(),
+        let offset = self.active_file.fd.stream_position()?;
+        serde_json::to_writer(&self.active_file.fd, &Command::Set(key.clone(), value))?;
+        writeln!(self.active_file.fd)?;
+        let old = self.keydir.insert(
+            key,
             KeyDirEntry {
                 file_id: self.active_file.path.clone(),
                 offset,
+                len: self.active_file.fd.stream_position()? - offset,
             },
         );
-        serde_json::to_writer(&self.active_file.fd, &Command::Set(key, value))?;
-        writeln!(self.active_file.fd)?;
+        if let Some(old) = old {
+            self.dead_bytes += old.len;
+        }
         self.uncompacted += offset;
 
-        if self.uncompacted > COMPACTION_THRESHOLD {
+        if self.uncompacted > self.compaction_threshold {
             self.compact()?;
         }
         Ok(())
@@ -104,25 +124,60 @@ impl KvStoreShared {
 
     /// Removes an item from the store
     fn remove(&mut self, key: String) -> Result<()> {
-        self.keydir.remove(&key).ok_or(Error::KeyNotFound)?;
+        let old = self.keydir.remove(&key).ok_or(Error::KeyNotFound)?;
+        let offset = self.active_file.fd.stream_position()?;
         serde_json::to_writer(&self.active_file.fd, &Command::Rm(key))?;
         writeln!(self.active_file.fd)?;
+        // Both the removed value and the removal itself are dead once written
+        self.dead_bytes += old.len + self.active_file.fd.stream_position()? - offset;
 
         Ok(())
     }
+
+    /// Collects all live items under a key prefix
+    fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        let mut keys: Vec<String> = self
+            .keydir
+            .keys()
+            .filter(|k| k.starts_with(&prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+
+        let mut items = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.get(key.clone())? {
+                items.push((key, value));
+            }
+        }
+        Ok(items)
+    }
 }
 
 impl KvStore {
     /// Opens the KvStore at a given path. Return the KvStore
     pub fn open(dir: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::open_with_threshold(dir, DEFAULT_COMPACTION_THRESHOLD)
+    }
+
+    /// Like `open`, but compacts once the active log file's size, added up at
+    /// every `set` since the last compaction, passes `compaction_threshold`.
+    /// That total grows with both the number of sets and the size of the
+    /// log, so it is passed long before as many bytes have been written.
+    pub fn open_with_threshold(
+        dir: impl Into<PathBuf>,
+        compaction_threshold: u64,
+    ) -> Result<KvStore> {
         let current_dir: PathBuf = dir.into();
         fs::create_dir_all(&current_dir)?;
 
         let files = get_sorted_files(current_dir.clone())?;
         let mut keydir = HashMap::new();
+        let mut disk_bytes = 0;
         // Slurp the serialized data from each file into hashmap
         for entry in &files {
             let fd = File::open(entry.path())?;
+            disk_bytes += fd.metadata()?.len();
             let mut it = serde_json::Deserializer::from_reader(&fd).into_iter::<Command>();
             let mut offset = it.byte_offset() as u64;
             while let Some(item) = it.next() {
@@ -133,6 +188,8 @@ impl KvStore {
                             KeyDirEntry {
                                 file_id: entry.path(),
                                 offset,
+                                // Each record is followed by a newline
+                                len: it.byte_offset() as u64 - offset + 1,
                             },
                         );
                     }
@@ -140,10 +197,14 @@ impl KvStore {
                         keydir.remove(&k);
                     }
                 }
-                offset = it.byte_offset() as u64;
+                // Skip the newline, so offsets point at the start of each record
+                offset = it.byte_offset() as u64 + 1;
             }
         }
 
+        let live_bytes: u64 = keydir.values().map(|entry| entry.len).sum();
+        let dead_bytes = disk_bytes.saturating_sub(live_bytes);
+
         if let Some(latest) = files.last() {
             let gen = latest.file_name().to_str().map_or(0, |e| {
                 e.split('.')
@@ -170,6 +231,12 @@ impl KvStore {
                 },
                 current_gen: gen,
                 uncompacted: 0,
+                generations: files.len() as u64,
+                dead_bytes,
+                compaction_threshold,
+                compactions: 0,
+                compaction_time: Duration::default(),
+                hub: WatchHub::default(),
             };
             kv.keydir = keydir;
 
@@ -182,6 +249,12 @@ impl KvStore {
                 active_file,
                 current_gen: 0,
                 uncompacted: 0,
+                generations: 1,
+                dead_bytes: 0,
+                compaction_threshold,
+                compactions: 0,
+                compaction_time: Duration::default(),
+                hub: WatchHub::default(),
             }))))
         }
     }
@@ -189,7 +262,10 @@ impl KvStore {
 
 impl KvsEngine for KvStore {
     fn set(&self, key: String, value: String) -> Result<()> {
-        self.0.lock().unwrap().set(key, value)
+        let mut store = self.0.lock().unwrap();
+        store.set(key.clone(), value.clone())?;
+        store.hub.publish(&key, Some(&value));
+        Ok(())
     }
 
     fn get(&self, key: String) -> Result<Option<String>> {
@@ -197,7 +273,38 @@ impl KvsEngine for KvStore {
     }
 
     fn remove(&self, key: String) -> Result<()> {
-        self.0.lock().unwrap().remove(key)
+        let mut store = self.0.lock().unwrap();
+        store.remove(key.clone())?;
+        store.hub.publish(&key, None);
+        Ok(())
+    }
+
+    fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        self.0.lock().unwrap().scan(prefix)
+    }
+
+    fn stats(&self) -> Result<EngineStats> {
+        let store = self.0.lock().unwrap();
+        Ok(EngineStats {
+            keys: store.keydir.len() as u64,
+            generations: store.generations,
+            dead_bytes: store.dead_bytes,
+            compactions: store.compactions,
+            compaction_seconds: store.compaction_time.as_secs_f64(),
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.0.lock().unwrap().active_file.fd.sync_all()?;
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.0.lock().unwrap().compact()
+    }
+
+    fn watch(&self, prefix: String) -> Result<Watcher> {
+        Ok(self.0.lock().unwrap().hub.watch(prefix))
     }
 }
 
@@ -205,6 +312,7 @@ impl KvsEngine for KvStore {
 struct KeyDirEntry {
     file_id: PathBuf,
     offset: u64,
+    len: u64,
 }
 
 pub struct ActiveFile {
@@ -236,7 +344,7 @@ fn get_sorted_files(current_dir: PathBuf) -> Result<Vec<DirEntry>> {
     let mut files: Vec<_> = fs::read_dir(&current_dir)?
         .filter_map(|e| e.ok())
         .filter(|e| {
-            e.file_type().map_or(false, |ft| ft.is_file())
+            e.file_type().is_ok_and(|ft| ft.is_file())
                 && e.path().extension() == Some(BUCKET_EXT.as_ref())
         })
         .collect();
@@ -0,0 +1,111 @@
+//! The manifest that records which engine a data directory belongs to.
+
+use crate::error::Error;
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// The on-disk format written by this version. Directories with a newer
+/// format are refused rather than risk misreading them.
+pub const FORMAT_VERSION: u32 = 1;
+/// The engine used for directories that have none recorded
+pub const DEFAULT_ENGINE: &str = "kvs";
+
+const ENGINES: [&str; 2] = ["kvs", "sled"];
+// Older servers marked the directory with a file holding just the engine name
+const LEGACY_ENGINE_FILE: &str = "engine";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub engine: String,
+    pub format_version: u32,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    /// Tells stores apart, e.g. in logs and metrics
+    pub store_id: String,
+}
+
+impl Manifest {
+    /// Opens the data directory `dir` for `engine`, creating the directory
+    /// and its manifest if need be. `None` takes the engine already recorded,
+    /// or `DEFAULT_ENGINE` for a new directory.
+    ///
+    /// Fails if `dir` belongs to another engine or was written in a newer format.
+    pub fn open(dir: &Path, engine: Option<&str>) -> Result<Manifest> {
+        fs::create_dir_all(dir)?;
+        let manifest = match Manifest::load(dir)? {
+            Some(manifest) => manifest,
+            None => {
+                let manifest = Manifest::new(engine.unwrap_or(DEFAULT_ENGINE))?;
+                manifest.save(dir)?;
+                manifest
+            }
+        };
+        match engine {
+            Some(engine) if engine != manifest.engine => Err(Error::DataDir(format!(
+                "{} holds a {} store, not {}",
+                dir.display(),
+                manifest.engine,
+                engine
+            ))),
+            _ => Ok(manifest),
+        }
+    }
+
+    /// Reads the manifest in `dir`, if it has one. A legacy `engine` file is
+    /// replaced with an equivalent manifest.
+    pub fn load(dir: &Path) -> Result<Option<Manifest>> {
+        let path = dir.join(MANIFEST_FILE);
+        if path.exists() {
+            let manifest: Manifest = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            if manifest.format_version > FORMAT_VERSION {
+                return Err(Error::DataDir(format!(
+                    "{} is in format {}, but only format {} and older are supported",
+                    dir.display(),
+                    manifest.format_version,
+                    FORMAT_VERSION
+                )));
+            }
+            return Ok(Some(manifest));
+        }
+
+        let legacy = dir.join(LEGACY_ENGINE_FILE);
+        if legacy.is_file() {
+            let manifest = Manifest::new(fs::read_to_string(&legacy)?.trim())?;
+            manifest.save(dir)?;
+            fs::remove_file(legacy)?;
+            return Ok(Some(manifest));
+        }
+        Ok(None)
+    }
+
+    fn new(engine: &str) -> Result<Manifest> {
+        if !ENGINES.contains(&engine) {
+            return Err(Error::InvalidEngine);
+        }
+        Ok(Manifest {
+            engine: engine.to_owned(),
+            format_version: FORMAT_VERSION,
+            created_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs(),
+            store_id: Uuid::new_v4().to_string(),
+        })
+    }
+
+    // Writes to a temporary file first, so a crash never leaves a torn manifest
+    fn save(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut file = File::create(&tmp)?;
+        serde_json::to_writer_pretty(&file, self)?;
+        writeln!(file)?;
+        file.sync_all()?;
+        fs::rename(tmp, dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+}
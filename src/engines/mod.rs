@@ -1,4 +1,5 @@
 use crate::Result;
+use serde::{Deserialize, Serialize};
 
 pub trait KvsEngine: Clone + Send + 'static {
     fn get(&self, key: String) -> Result<Option<String>>;
@@ -6,10 +7,43 @@ pub trait KvsEngine: Clone + Send + 'static {
     fn set(&self, key: String, value: String) -> Result<()>;
 
     fn remove(&self, key: String) -> Result<()>;
+
+    /// Returns every key/value pair whose key starts with `prefix`, ordered by key
+    fn scan(&self, prefix: String) -> Result<Vec<(String, String)>>;
+
+    fn stats(&self) -> Result<EngineStats>;
+
+    /// Makes every completed write durable
+    fn flush(&self) -> Result<()>;
+
+    /// Reclaims the space taken by overwritten and removed values now,
+    /// rather than when the engine next decides to
+    fn compact(&self) -> Result<()>;
+
+    /// Subscribes to every later write to a key starting with `prefix`
+    fn watch(&self, prefix: String) -> Result<Watcher>;
+}
+
+/// A point-in-time snapshot of an engine's state
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EngineStats {
+    pub keys: u64,
+    /// Log files on disk. The fields below are zero for engines that don't track them.
+    pub generations: u64,
+    /// Bytes on disk taken up by overwritten and removed values
+    pub dead_bytes: u64,
+    pub compactions: u64,
+    pub compaction_seconds: f64,
 }
 
+mod async_engine;
 mod kvs;
+mod manifest;
 mod sled;
+mod watch;
 
+pub use self::async_engine::AsyncKvsEngine;
 pub use self::kvs::{KvStore, BUCKET_EXT};
+pub use self::manifest::{Manifest, DEFAULT_ENGINE, FORMAT_VERSION, MANIFEST_FILE};
 pub use self::sled::SledKvsEngine;
+pub use self::watch::{Event, Watcher, WATCHER_CAPACITY};
@@ -1,20 +1,60 @@
-use super::KvsEngine;
+use super::watch::{WatchHub, Watcher};
+use super::{EngineStats, KvsEngine};
 use crate::error;
 use crate::Result;
-use sled::{Db, Tree};
+use sled::{Db, Event, Tree};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
 
 #[derive(Clone)]
-pub struct SledKvsEngine(Db);
+pub struct SledKvsEngine {
+    db: Db,
+    // Counted once on open and kept up to date by writes, as sled can only
+    // count its keys by walking the whole tree
+    keys: Arc<AtomicU64>,
+    hub: Arc<Mutex<WatchHub>>,
+    pump: Arc<Once>,
+}
 
 impl SledKvsEngine {
     pub fn new(db: Db) -> Self {
-        SledKvsEngine(db)
+        SledKvsEngine {
+            keys: Arc::new(AtomicU64::new(db.len() as u64)),
+            db,
+            hub: Arc::default(),
+            pump: Arc::new(Once::new()),
+        }
+    }
+
+    /// Feeds sled's own change stream into the hub, so that every watcher
+    /// sees the same versions no matter which clone of the engine wrote
+    fn start_pump(&self) {
+        let subscriber = self.db.watch_prefix(vec![]);
+        let hub = self.hub.clone();
+        thread::spawn(move || {
+            for event in subscriber {
+                let (key, value) = match &event {
+                    Event::Insert { key, value } => (key, Some(value)),
+                    Event::Remove { key } => (key, None),
+                };
+                let key = match std::str::from_utf8(key) {
+                    Ok(key) => key,
+                    Err(_) => continue,
+                };
+                let value = match value.map(|value| std::str::from_utf8(value)).transpose() {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+                hub.lock().unwrap().publish(key, value);
+            }
+        });
     }
 }
 
 impl KvsEngine for SledKvsEngine {
     fn get(&self, key: String) -> Result<Option<String>> {
-        let tree: &Tree = &self.0;
+        let tree: &Tree = &self.db;
         Ok(tree
             .get(key)?
             .map(|i_vec| AsRef::<[u8]>::as_ref(&i_vec).to_vec())
@@ -23,16 +63,54 @@ impl KvsEngine for SledKvsEngine {
     }
 
     fn set(&self, key: String, value: String) -> Result<()> {
-        let tree: &Tree = &self.0;
-        tree.insert(key, value.into_bytes()).map(|_| ())?;
+        let tree: &Tree = &self.db;
+        if tree.insert(key, value.into_bytes())?.is_none() {
+            self.keys.fetch_add(1, Ordering::Relaxed);
+        }
         tree.flush()?;
         Ok(())
     }
 
     fn remove(&self, key: String) -> Result<()> {
-        let tree: &Tree = &self.0;
+        let tree: &Tree = &self.db;
         tree.remove(key)?.ok_or(error::Error::KeyNotFound)?;
+        self.keys.fetch_sub(1, Ordering::Relaxed);
         tree.flush()?;
         Ok(())
     }
+
+    fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        let tree: &Tree = &self.db;
+        tree.scan_prefix(prefix)
+            .map(|item| {
+                let (key, value) = item?;
+                Ok((
+                    String::from_utf8(key.to_vec())?,
+                    String::from_utf8(value.to_vec())?,
+                ))
+            })
+            .collect()
+    }
+
+    fn stats(&self) -> Result<EngineStats> {
+        Ok(EngineStats {
+            keys: self.keys.load(Ordering::Relaxed),
+            ..EngineStats::default()
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<()> {
+        // sled reclaims space on its own as it flushes; there is nothing to force
+        self.flush()
+    }
+
+    fn watch(&self, prefix: String) -> Result<Watcher> {
+        self.pump.call_once(|| self.start_pump());
+        Ok(self.hub.lock().unwrap().watch(prefix))
+    }
 }
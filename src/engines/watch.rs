@@ -0,0 +1,105 @@
+//! Change notifications behind `KvsEngine::watch`.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Events a watcher may fall behind by before its watch is ended
+pub const WATCHER_CAPACITY: usize = 1024;
+
+/// A completed write to one key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub key: String,
+    /// The new value, or `None` if the key was removed
+    pub value: Option<String>,
+    /// Counts every write the engine has published since it was opened
+    pub version: u64,
+}
+
+/// Receives the writes under a watched prefix, in the order they happened.
+/// Dropping it unsubscribes.
+///
+/// A watcher more than `WATCHER_CAPACITY` events behind is unsubscribed and
+/// marked `overflowed` rather than left to buffer without bound. It then ends
+/// after the events it already holds.
+pub struct Watcher {
+    events: Receiver<Event>,
+    overflowed: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    /// Waits up to `timeout` for the next event
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        self.events.recv_timeout(timeout)
+    }
+
+    /// Whether the watch was ended for falling too far behind, so events were missed
+    pub fn overflowed(&self) -> bool {
+        self.overflowed.load(Ordering::SeqCst)
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.events.recv().ok()
+    }
+}
+
+/// Fans each write out to the watchers whose prefix matches its key
+#[derive(Default)]
+pub(crate) struct WatchHub {
+    version: u64,
+    watchers: Vec<Subscription>,
+}
+
+struct Subscription {
+    prefix: String,
+    events: SyncSender<Event>,
+    overflowed: Arc<AtomicBool>,
+}
+
+impl WatchHub {
+    pub fn watch(&mut self, prefix: String) -> Watcher {
+        let (tx, rx) = mpsc::sync_channel(WATCHER_CAPACITY);
+        let overflowed = Arc::new(AtomicBool::new(false));
+        self.watchers.push(Subscription {
+            prefix,
+            events: tx,
+            overflowed: Arc::clone(&overflowed),
+        });
+        Watcher {
+            events: rx,
+            overflowed,
+        }
+    }
+
+    /// Sends the write to every matching watcher without blocking the writer,
+    /// dropping watchers that have left or fallen behind
+    pub fn publish(&mut self, key: &str, value: Option<&str>) {
+        self.version += 1;
+        let version = self.version;
+        self.watchers.retain(|watcher| {
+            if !key.starts_with(watcher.prefix.as_str()) {
+                return true;
+            }
+            let event = Event {
+                key: key.to_owned(),
+                value: value.map(str::to_owned),
+                version,
+            };
+            match watcher.events.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    watcher.overflowed.store(true, Ordering::SeqCst);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
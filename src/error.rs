@@ -28,8 +28,30 @@ pub enum Error {
     InvalidEngine,
     #[error("Unspecified")]
     Unspecified,
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Rejected: {0}")]
+    Rejected(String),
+    #[error("Server overloaded, retry after {0:?}")]
+    Overloaded(std::time::Duration),
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    #[error("Job queue full")]
+    QueueFull,
+    #[error("Thread pool shut down")]
+    PoolShutDown,
     #[error(transparent)]
     Rayon(#[from] ThreadPoolBuildError),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
+    #[error("TLS configuration: {0}")]
+    TlsConfig(String),
+    #[error("Configuration: {0}")]
+    Config(String),
+    #[error("Data directory: {0}")]
+    DataDir(String),
 }
 
 /// The Result type encapsulates standard result
@@ -0,0 +1,149 @@
+//! Wire framing shared by the blocking and async servers and clients.
+//!
+//! Each message is a header of the byte `K`, the protocol version and a
+//! big-endian `u32` length, followed by that many bytes of bincode. The
+//! explicit length lets readers find message boundaries without driving the
+//! bincode deserializer from a socket.
+//!
+//! Version 1 replaced the bare bincode messages of earlier releases, which
+//! carried no header. Peers speaking another version, or no version at all,
+//! are refused with an error rather than misread.
+
+use crate::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The version of the wire protocol spoken by this build's servers and clients
+pub const PROTOCOL_VERSION: u8 = 1;
+
+const MAGIC: u8 = b'K';
+
+/// Bytes in a frame's header, ahead of its body
+pub(crate) const HEADER_LEN: u64 = 6;
+
+/// Frames larger than this are refused unless a caller sets its own limit
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Reads one frame of at most `max_len` bytes. Returns `None` if the peer
+/// closed the stream before sending one.
+pub(crate) fn read_frame<T: DeserializeOwned, R: Read>(
+    mut reader: R,
+    max_len: u32,
+) -> Result<Option<T>> {
+    match read_header(&mut reader)? {
+        Some(len) => {
+            check_len(len, max_len)?;
+            read_body(reader, len).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Reads a frame's length, so callers can vet it before anything is allocated
+pub(crate) fn read_header<R: Read>(mut reader: R) -> Result<Option<u32>> {
+    let mut header = [0; HEADER_LEN as usize];
+    match reader.read_exact(&mut header) {
+        Ok(()) => parse_header(header).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) fn read_body<T: DeserializeOwned, R: Read>(mut reader: R, len: u32) -> Result<T> {
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(bincode::deserialize(&buf)?)
+}
+
+/// Writes one frame, returning its size in bytes
+pub(crate) fn write_frame<T: Serialize, W: Write>(mut writer: W, message: &T) -> Result<u64> {
+    let buf = encode(message)?;
+    writer.write_all(&buf)?;
+    writer.flush()?;
+    Ok(buf.len() as u64)
+}
+
+pub(crate) async fn read_frame_async<T, R>(reader: &mut R, max_len: u32) -> Result<Option<T>>
+where
+    T: DeserializeOwned,
+    R: AsyncRead + Unpin,
+{
+    match read_header_async(reader).await? {
+        Some(len) => {
+            check_len(len, max_len)?;
+            read_body_async(reader, len).await.map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+pub(crate) async fn read_header_async<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<u32>> {
+    let mut header = [0; HEADER_LEN as usize];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => parse_header(header).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) async fn read_body_async<T, R>(reader: &mut R, len: u32) -> Result<T>
+where
+    T: DeserializeOwned,
+    R: AsyncRead + Unpin,
+{
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf).await?;
+    Ok(bincode::deserialize(&buf)?)
+}
+
+pub(crate) async fn write_frame_async<T, W>(writer: &mut W, message: &T) -> Result<u64>
+where
+    T: Serialize,
+    W: AsyncWrite + Unpin,
+{
+    let buf = encode(message)?;
+    writer.write_all(&buf).await?;
+    writer.flush().await?;
+    Ok(buf.len() as u64)
+}
+
+fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>> {
+    let len = bincode::serialized_size(message)?;
+    let len = u32::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+    let mut buf = Vec::with_capacity(HEADER_LEN as usize + len as usize);
+    buf.extend_from_slice(&[MAGIC, PROTOCOL_VERSION]);
+    buf.extend_from_slice(&len.to_be_bytes());
+    bincode::serialize_into(&mut buf, message)?;
+    Ok(buf)
+}
+
+fn parse_header(header: [u8; HEADER_LEN as usize]) -> Result<u32> {
+    let [magic, version, len @ ..] = header;
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    if magic != MAGIC {
+        return Err(invalid("peer is not speaking the kvs protocol".to_owned()).into());
+    }
+    if version != PROTOCOL_VERSION {
+        return Err(invalid(format!(
+            "peer speaks protocol version {}, not {}",
+            version, PROTOCOL_VERSION
+        ))
+        .into());
+    }
+    Ok(u32::from_be_bytes(len))
+}
+
+fn check_len(len: u32, max_len: u32) -> Result<()> {
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds the {} byte limit", len, max_len),
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+//! An HTTP/JSON gateway onto a `KvsEngine`.
+//!
+//! Routes:
+//! - `GET|PUT|DELETE /keys/{key}`, where `PUT` takes the value as a JSON string body
+//! - `GET /keys?prefix=` to scan a key range
+//! - `GET /health` and `GET /stats`
+//!
+//! Key operations answer with a JSON-encoded `command::Response`.
+
+use crate::command::Response;
+use crate::engines::{EngineStats, KvsEngine};
+use crate::error::Error;
+use crate::http::{self, HttpRequest, HttpResponse};
+use crate::server::{Limits, REJECT_BACKLOG, REJECT_TIMEOUT};
+use crate::shutdown::InFlight;
+use crate::Result;
+use crate::ThreadPool;
+use serde::Serialize;
+use slog::{error, info, warn};
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+pub struct HttpGateway<E: KvsEngine, P: ThreadPool> {
+    engine: E,
+    thread_pool: P,
+    logger: slog::Logger,
+    limits: Limits,
+    counters: Arc<Counters>,
+    in_flight: InFlight,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+#[derive(Serialize)]
+struct GatewayStats {
+    requests: u64,
+    client_errors: u64,
+    server_errors: u64,
+    engine: EngineStats,
+}
+
+impl<E: KvsEngine, P: ThreadPool> HttpGateway<E, P> {
+    pub fn new(engine: E, thread_pool: P, logger: slog::Logger) -> HttpGateway<E, P> {
+        HttpGateway::with_limits(engine, thread_pool, logger, Limits::default())
+    }
+
+    /// Creates a gateway that holds connections to the timeouts and
+    /// `max_connections` in `limits`, and refuses keys and values over its
+    /// sizes with `413`. Bodies are capped at `limits.max_frame_size`.
+    pub fn with_limits(
+        engine: E,
+        thread_pool: P,
+        logger: slog::Logger,
+        limits: Limits,
+    ) -> HttpGateway<E, P> {
+        HttpGateway {
+            engine,
+            thread_pool,
+            logger,
+            limits,
+            counters: Arc::new(Counters::default()),
+            in_flight: InFlight::default(),
+        }
+    }
+
+    pub fn start<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
+        let connection = TcpListener::bind(addr)?;
+        info!(self.logger, "starting http gateway...");
+
+        // Rejections read the request before answering so the client sees the
+        // `503` rather than a reset, which must not hold up accepting
+        let (reject_tx, reject_rx) = mpsc::sync_channel::<TcpStream>(REJECT_BACKLOG);
+        {
+            let limits = self.limits.clone();
+            let logger = self.logger.clone();
+            thread::spawn(move || {
+                for stream in reject_rx {
+                    if let Err(e) = reject(stream, &limits) {
+                        info!(logger, "failed to send rejection: {}", e);
+                    }
+                }
+            });
+        }
+
+        for stream in connection.incoming() {
+            if let Some(max) = self.limits.max_connections {
+                if self.in_flight.count() >= max {
+                    warn!(self.logger, "too many connections, rejecting"; "max_connections" => max);
+                    if let Ok(stream) = stream {
+                        // Past the backlog the connection is just dropped
+                        let _ = reject_tx.try_send(stream);
+                    }
+                    continue;
+                }
+            }
+
+            let engine = self.engine.clone();
+            let counters = self.counters.clone();
+            let logger = self.logger.clone();
+            let limits = self.limits.clone();
+            let in_flight = self.in_flight.enter();
+            self.thread_pool.spawn(move || {
+                match stream {
+                    Ok(stream) => match serve(engine, &counters, stream, &limits, &logger) {
+                        Ok(()) => {}
+                        Err(Error::Io(e))
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            info!(logger, "closing connection after timeout");
+                        }
+                        Err(e) => error!(logger, "ERROR serving http connection: {}", e),
+                    },
+                    Err(stream_err) => {
+                        error!(logger, "ERROR connecting to stream: {}", stream_err)
+                    }
+                }
+                drop(in_flight);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Answers a connection over `max_connections` with `503`
+fn reject(stream: TcpStream, limits: &Limits) -> Result<()> {
+    stream.set_read_timeout(Some(REJECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(REJECT_TIMEOUT))?;
+    http::read_request(&stream, limits.max_frame_size as usize)?;
+    HttpResponse::json(
+        503,
+        &Response::Rejected("too many open connections".to_owned()),
+    )
+    .write_to(&stream)?;
+    Ok(())
+}
+
+fn serve<E: KvsEngine>(
+    engine: E,
+    counters: &Counters,
+    stream: TcpStream,
+    limits: &Limits,
+    logger: &slog::Logger,
+) -> Result<()> {
+    // Wait out the idle timeout for the request to start, then the read
+    // timeout for the rest of it
+    stream.set_read_timeout(limits.idle_timeout)?;
+    if stream.peek(&mut [0])? == 0 {
+        return Ok(());
+    }
+    stream.set_read_timeout(limits.read_timeout)?;
+    stream.set_write_timeout(limits.write_timeout)?;
+
+    let response = match http::read_request(&stream, limits.max_frame_size as usize) {
+        Ok(request) => {
+            info!(logger, "HTTP request"; "method" => request.method.as_str());
+            route(&engine, counters, limits, request).unwrap_or_else(|e| {
+                error!(logger, "ERROR handling http request: {}", e);
+                HttpResponse::json(500, &Response::Error(e.to_string()))
+            })
+        }
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            HttpResponse::json(400, &Response::Error(e.to_string()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    counters.requests.fetch_add(1, Ordering::Relaxed);
+    match response.status {
+        400..=499 => counters.client_errors.fetch_add(1, Ordering::Relaxed),
+        500..=599 => counters.server_errors.fetch_add(1, Ordering::Relaxed),
+        _ => 0,
+    };
+    response.write_to(&stream)?;
+    Ok(())
+}
+
+fn route<E: KvsEngine>(
+    engine: &E,
+    counters: &Counters,
+    limits: &Limits,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let segments: Vec<&str> = request.segments.iter().map(String::as_str).collect();
+    if let ["keys", key] = segments.as_slice() {
+        if let Some(rejection) = limits.check_entry(key, None) {
+            return Ok(HttpResponse::json(413, &rejection));
+        }
+    }
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["health"]) => Ok(HttpResponse::json(200, &Response::OK("healthy".to_owned()))),
+        ("GET", ["stats"]) => Ok(HttpResponse::json(
+            200,
+            &GatewayStats {
+                requests: counters.requests.load(Ordering::Relaxed),
+                client_errors: counters.client_errors.load(Ordering::Relaxed),
+                server_errors: counters.server_errors.load(Ordering::Relaxed),
+                engine: engine.stats()?,
+            },
+        )),
+        ("GET", ["keys"]) => {
+            let prefix = request.query.get("prefix").cloned().unwrap_or_default();
+            Ok(HttpResponse::json(
+                200,
+                &Response::Entries(engine.scan(prefix)?),
+            ))
+        }
+        ("GET", ["keys", key]) => match engine.get(key.to_string())? {
+            Some(value) => Ok(HttpResponse::json(200, &Response::OK(value))),
+            None => Ok(HttpResponse::json(404, &Response::NotFound)),
+        },
+        ("PUT", ["keys", key]) => match serde_json::from_slice::<String>(&request.body) {
+            Ok(value) => {
+                if let Some(rejection) = limits.check_entry(key, Some(&value)) {
+                    return Ok(HttpResponse::json(413, &rejection));
+                }
+                engine.set(key.to_string(), value)?;
+                Ok(HttpResponse::json(200, &Response::OK("".to_owned())))
+            }
+            Err(e) => Ok(HttpResponse::json(
+                400,
+                &Response::Error(format!("body must be a JSON string: {}", e)),
+            )),
+        },
+        ("DELETE", ["keys", key]) => match engine.remove(key.to_string()) {
+            Ok(()) => Ok(HttpResponse::json(200, &Response::OK("".to_owned()))),
+            Err(Error::KeyNotFound) => Ok(HttpResponse::json(404, &Response::NotFound)),
+            Err(e) => Err(e),
+        },
+        (_, ["health"]) | (_, ["stats"]) | (_, ["keys"]) | (_, ["keys", _]) => Ok(
+            HttpResponse::json(405, &Response::Error("method not allowed".to_owned())),
+        ),
+        _ => Ok(HttpResponse::json(
+            404,
+            &Response::Error("no such route".to_owned()),
+        )),
+    }
+}
@@ -1,13 +1,31 @@
 //! This library houses a key-value store
 
+mod async_client;
+pub mod async_server;
+pub mod auth;
 mod client;
 pub mod command;
+mod connections;
 pub mod engines;
 mod error;
+mod frame;
+pub mod gateway;
+mod http;
+pub mod logging;
+pub mod memcached;
+pub mod metrics;
+pub mod rate_limit;
 pub mod server;
+mod shutdown;
 pub mod thread_pool;
+pub mod tls;
+pub mod transport;
 
-pub use client::KvsClient;
+pub use async_client::AsyncKvsClient;
+pub use client::{KvsClient, KvsClientBuilder, WatchStream};
 pub use engines::{KvStore, KvsEngine, SledKvsEngine};
-pub use error::Result;
+pub use error::{Error, Result};
+pub use frame::PROTOCOL_VERSION;
+pub use shutdown::ShutdownHandle;
 pub use thread_pool::{NaiveThreadPool, ThreadPool};
+pub use transport::Address;
@@ -0,0 +1,176 @@
+//! Log output and redaction shared by the servers.
+
+use slog::{Drain, Level, OwnedKVList, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const REDACTED: &str = "<redacted>";
+
+/// How much of the data in requests the servers write to their logs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogData {
+    /// Neither keys nor values
+    #[default]
+    Redacted,
+    Keys,
+    /// Keys and values
+    All,
+}
+
+impl LogData {
+    pub(crate) fn key(self, key: &str) -> &str {
+        if self >= LogData::Keys {
+            key
+        } else {
+            REDACTED
+        }
+    }
+
+    pub(crate) fn value(self, value: &str) -> &str {
+        if self == LogData::All {
+            value
+        } else {
+            REDACTED
+        }
+    }
+}
+
+impl FromStr for LogData {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogData, String> {
+        match s {
+            "redacted" => Ok(LogData::Redacted),
+            "keys" => Ok(LogData::Keys),
+            "all" => Ok(LogData::All),
+            _ => Err(format!("{} is not one of redacted, keys or all", s)),
+        }
+    }
+}
+
+/// A log file that moves aside to `PATH.1` once it grows past `max_size`,
+/// shifting older files up to `PATH.{keep}` and deleting the one after.
+///
+/// Rotation happens on `flush`, so a record flushed as a whole is never
+/// split across files. A failed rotation is reported on stderr rather than
+/// returned, so it doesn't take the logger down; logging carries on in the
+/// current file and rotation is tried again after another `max_size` bytes.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: u32,
+}
+
+impl RotatingFile {
+    pub fn open(path: impl Into<PathBuf>, max_size: u64, keep: u32) -> io::Result<RotatingFile> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.size >= self.max_size {
+            if let Err(e) = self.rotate() {
+                eprintln!("failed to rotate {}: {}", self.path.display(), e);
+                self.size = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Like `slog::LevelFilter`, but its level can be changed through a
+/// `LevelSwitch` while the logger is in use
+pub struct SwitchableLevel<D> {
+    drain: D,
+    level: LevelSwitch,
+}
+
+/// Changes the level of the `SwitchableLevel` it came from
+#[derive(Clone)]
+pub struct LevelSwitch(Arc<AtomicUsize>);
+
+impl<D: Drain> SwitchableLevel<D> {
+    pub fn new(drain: D, level: Level) -> SwitchableLevel<D> {
+        SwitchableLevel {
+            drain,
+            level: LevelSwitch(Arc::new(AtomicUsize::new(level.as_usize()))),
+        }
+    }
+
+    pub fn switch(&self) -> LevelSwitch {
+        self.level.clone()
+    }
+}
+
+impl LevelSwitch {
+    /// Logs records at `level` and anything more severe from now on
+    pub fn set(&self, level: Level) {
+        self.0.store(level.as_usize(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Level {
+        Level::from_usize(self.0.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+    }
+}
+
+impl<D: Drain> Drain for SwitchableLevel<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        if record.level().is_at_least(self.level.get()) {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
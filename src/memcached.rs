@@ -0,0 +1,558 @@
+//! A memcached ASCII protocol front-end translating onto a `KvsEngine`.
+//!
+//! Values are stored in the engine as-is; memcached-only metadata (flags,
+//! expiry and cas uniques) lives in an in-memory side table, so it does not
+//! survive a restart. Because engines store `String`s, data blocks must be
+//! valid UTF-8.
+
+use crate::engines::KvsEngine;
+use crate::error::Error;
+use crate::server::Limits;
+use crate::shutdown::InFlight;
+use crate::Result;
+use crate::ThreadPool;
+use slog::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAX_KEY_LENGTH: usize = 250;
+const MAX_LINE_LENGTH: u64 = 2048;
+// exptime values above this are absolute unix timestamps
+const RELATIVE_EXPTIME_LIMIT: i64 = 60 * 60 * 24 * 30;
+
+pub struct MemcachedServer<E: KvsEngine, P: ThreadPool> {
+    engine: E,
+    thread_pool: P,
+    logger: slog::Logger,
+    limits: Limits,
+    meta: MetaTable,
+    in_flight: InFlight,
+}
+
+impl<E: KvsEngine, P: ThreadPool> MemcachedServer<E, P> {
+    pub fn new(engine: E, thread_pool: P, logger: slog::Logger) -> MemcachedServer<E, P> {
+        MemcachedServer::with_limits(engine, thread_pool, logger, Limits::default())
+    }
+
+    /// Creates a server that holds connections to the timeouts and
+    /// `max_connections` in `limits`, and refuses items larger than
+    /// `limits.max_value_size`
+    pub fn with_limits(
+        engine: E,
+        thread_pool: P,
+        logger: slog::Logger,
+        limits: Limits,
+    ) -> MemcachedServer<E, P> {
+        MemcachedServer {
+            engine,
+            thread_pool,
+            logger,
+            limits,
+            meta: MetaTable::default(),
+            in_flight: InFlight::default(),
+        }
+    }
+
+    pub fn start<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
+        let connection = TcpListener::bind(addr)?;
+        info!(self.logger, "starting memcached listener...");
+
+        for stream in connection.incoming() {
+            if let Some(max) = self.limits.max_connections {
+                if self.in_flight.count() >= max {
+                    warn!(self.logger, "too many connections, rejecting"; "max_connections" => max);
+                    if let Ok(stream) = stream {
+                        reject(stream, &self.limits);
+                    }
+                    continue;
+                }
+            }
+
+            let engine = self.engine.clone();
+            let meta = self.meta.clone();
+            let logger = self.logger.clone();
+            let limits = self.limits.clone();
+            let in_flight = self.in_flight.enter();
+            self.thread_pool.spawn(move || {
+                match stream {
+                    Ok(stream) => match serve(engine, meta, stream, &limits, &logger) {
+                        Ok(()) => {}
+                        Err(Error::Io(e))
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            info!(logger, "closing connection after timeout");
+                        }
+                        Err(e) => error!(logger, "ERROR serving memcached connection: {}", e),
+                    },
+                    Err(stream_err) => {
+                        error!(logger, "ERROR connecting to stream: {}", stream_err)
+                    }
+                }
+                drop(in_flight);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Tells a connection over `max_connections` why it is being closed, as
+/// memcached does, without waiting on it for long
+fn reject(mut stream: TcpStream, limits: &Limits) {
+    let _ = stream.set_write_timeout(limits.write_timeout);
+    let _ = stream.write_all(b"SERVER_ERROR too many open connections\r\n");
+}
+
+#[derive(Clone, Copy)]
+struct ItemMeta {
+    flags: u32,
+    expires_at: Option<SystemTime>,
+    cas: u64,
+}
+
+impl ItemMeta {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= SystemTime::now())
+    }
+}
+
+/// Memcached metadata for each item, and the keys commands are working on.
+/// A command holds its key's `KeyLock` across its engine calls, so commands on
+/// one key stay atomic while those on different keys run concurrently.
+#[derive(Clone, Default)]
+struct MetaTable(Arc<MetaTableShared>);
+
+#[derive(Default)]
+struct MetaTableShared {
+    items: Mutex<Items>,
+    busy: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct Items {
+    items: HashMap<String, ItemMeta>,
+    last_cas: u64,
+}
+
+impl Items {
+    fn next_cas(&mut self) -> u64 {
+        self.last_cas += 1;
+        self.last_cas
+    }
+}
+
+impl MetaTable {
+    /// Waits until no other command is working on `key`, then claims it
+    fn lock(&self, key: &str) -> KeyLock<'_> {
+        let shared = &*self.0;
+        let mut busy = shared.busy.lock().unwrap();
+        while busy.contains(key) {
+            busy = shared.released.wait(busy).unwrap();
+        }
+        busy.insert(key.to_owned());
+        KeyLock {
+            shared,
+            key: key.to_owned(),
+        }
+    }
+}
+
+/// A claim on one key. The table's own mutex is only held for map updates,
+/// never across engine calls.
+struct KeyLock<'a> {
+    shared: &'a MetaTableShared,
+    key: String,
+}
+
+impl KeyLock<'_> {
+    fn items(&self) -> MutexGuard<'_, Items> {
+        self.shared.items.lock().unwrap()
+    }
+
+    /// Looks up the live item, lazily evicting it once expired
+    fn lookup<E: KvsEngine>(&self, engine: &E) -> Result<Option<(String, ItemMeta)>> {
+        let key = &self.key;
+        let expired = self
+            .items()
+            .items
+            .get(key)
+            .is_some_and(ItemMeta::is_expired);
+        if expired {
+            self.items().items.remove(key);
+            remove_ignoring_missing(engine, key)?;
+            return Ok(None);
+        }
+
+        match engine.get(key.clone())? {
+            Some(value) => {
+                let mut items = self.items();
+                let meta = match items.items.get(key) {
+                    Some(meta) => *meta,
+                    None => {
+                        // Written through another front-end; adopt it with default metadata
+                        let meta = ItemMeta {
+                            flags: 0,
+                            expires_at: None,
+                            cas: items.next_cas(),
+                        };
+                        items.items.insert(key.clone(), meta);
+                        meta
+                    }
+                };
+                Ok(Some((value, meta)))
+            }
+            None => {
+                self.items().items.remove(key);
+                Ok(None)
+            }
+        }
+    }
+
+    fn store<E: KvsEngine>(
+        &self,
+        engine: &E,
+        value: String,
+        flags: u32,
+        expires_at: Option<SystemTime>,
+    ) -> Result<()> {
+        let key = &self.key;
+        if expires_at.is_some_and(|at| at <= SystemTime::now()) {
+            // Already expired: memcached accepts the write but the item is never visible
+            self.items().items.remove(key);
+            return remove_ignoring_missing(engine, key);
+        }
+        engine.set(key.clone(), value)?;
+        let mut items = self.items();
+        let cas = items.next_cas();
+        items.items.insert(
+            key.clone(),
+            ItemMeta {
+                flags,
+                expires_at,
+                cas,
+            },
+        );
+        Ok(())
+    }
+
+    fn delete<E: KvsEngine>(&self, engine: &E) -> Result<()> {
+        self.items().items.remove(&self.key);
+        engine.remove(self.key.clone())
+    }
+}
+
+impl Drop for KeyLock<'_> {
+    fn drop(&mut self) {
+        self.shared.busy.lock().unwrap().remove(&self.key);
+        self.shared.released.notify_all();
+    }
+}
+
+fn remove_ignoring_missing<E: KvsEngine>(engine: &E, key: &str) -> Result<()> {
+    match engine.remove(key.to_owned()) {
+        Ok(()) | Err(Error::KeyNotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StoreMode {
+    Set,
+    Add,
+    Replace,
+    Cas(u64),
+}
+
+struct StoreCommand {
+    mode: StoreMode,
+    key: String,
+    flags: u32,
+    exptime: i64,
+    bytes: usize,
+    noreply: bool,
+}
+
+fn serve<E: KvsEngine>(
+    engine: E,
+    meta: MetaTable,
+    stream: TcpStream,
+    limits: &Limits,
+    logger: &slog::Logger,
+) -> Result<()> {
+    info!(logger, "accepting incoming memcached connection...");
+    stream.set_write_timeout(limits.write_timeout)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        // Clients may sit quietly between commands for `idle_timeout`, but
+        // once one starts it has `read_timeout` to finish
+        reader.get_ref().set_read_timeout(limits.idle_timeout)?;
+        if reader.fill_buf()?.is_empty() {
+            return Ok(());
+        }
+        reader.get_ref().set_read_timeout(limits.read_timeout)?;
+
+        let mut line = String::new();
+        let read = (&mut reader).take(MAX_LINE_LENGTH).read_line(&mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        if !line.ends_with('\n') {
+            writer.write_all(b"CLIENT_ERROR line too long\r\n")?;
+            return Ok(());
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let reply = match tokens.split_first() {
+            None => Ok(Some("ERROR\r\n".to_owned())),
+            Some((&"quit", _)) => return Ok(()),
+            Some((&"version", _)) => Ok(Some(format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")))),
+            Some((&"get", keys)) => retrieve(&engine, &meta, keys, false).map(Some),
+            Some((&"gets", keys)) => retrieve(&engine, &meta, keys, true).map(Some),
+            Some((&"delete", args)) => delete(&engine, &meta, args),
+            Some((&"incr", args)) => incr_decr(&engine, &meta, args, true),
+            Some((&"decr", args)) => incr_decr(&engine, &meta, args, false),
+            Some((cmd @ (&"set" | &"add" | &"replace" | &"cas"), args)) => {
+                match parse_store_command(cmd, args) {
+                    Ok(command) if command.bytes > limits.max_value_size => {
+                        writer.write_all(b"SERVER_ERROR object too large for cache\r\n")?;
+                        skip_data_block(&mut reader, command.bytes)?;
+                        Ok(None)
+                    }
+                    Ok(command) => {
+                        let data = match read_data_block(&mut reader, command.bytes)? {
+                            Some(data) => data,
+                            None => {
+                                writer.write_all(b"CLIENT_ERROR bad data chunk\r\n")?;
+                                continue;
+                            }
+                        };
+                        store(&engine, &meta, &command, data)
+                            .map(|reply| Some(reply).filter(|_| !command.noreply))
+                    }
+                    Err(e) => Ok(Some(format!("CLIENT_ERROR {}\r\n", e))),
+                }
+            }
+            Some(_) => Ok(Some("ERROR\r\n".to_owned())),
+        };
+
+        // Engine failures are the server's, not the connection's, so the client hears
+        // about them and may carry on
+        let reply = reply.unwrap_or_else(|e| {
+            error!(logger, "ERROR running memcached command: {}", e);
+            Some(format!(
+                "SERVER_ERROR {}\r\n",
+                e.to_string().replace(['\r', '\n'], " ")
+            ))
+        });
+        if let Some(reply) = reply {
+            writer.write_all(reply.as_bytes())?;
+        }
+    }
+}
+
+fn parse_store_command(
+    cmd: &str,
+    args: &[&str],
+) -> std::result::Result<StoreCommand, &'static str> {
+    let expected = if cmd == "cas" { 5 } else { 4 };
+    if args.len() != expected && args.len() != expected + 1 {
+        return Err("bad command line format");
+    }
+    let key = parse_key(args[0])?;
+    let flags = args[1].parse().map_err(|_| "bad command line format")?;
+    let exptime = args[2].parse().map_err(|_| "bad command line format")?;
+    let bytes = args[3].parse().map_err(|_| "bad command line format")?;
+    let mode = match cmd {
+        "set" => StoreMode::Set,
+        "add" => StoreMode::Add,
+        "replace" => StoreMode::Replace,
+        _ => StoreMode::Cas(args[4].parse().map_err(|_| "bad command line format")?),
+    };
+    let noreply = match args.get(expected) {
+        Some(&"noreply") => true,
+        Some(_) => return Err("bad command line format"),
+        None => false,
+    };
+
+    Ok(StoreCommand {
+        mode,
+        key,
+        flags,
+        exptime,
+        bytes,
+        noreply,
+    })
+}
+
+fn parse_key(key: &str) -> std::result::Result<String, &'static str> {
+    if key.len() > MAX_KEY_LENGTH || key.chars().any(char::is_control) {
+        return Err("bad command line format");
+    }
+    Ok(key.to_owned())
+}
+
+/// The length of a `<bytes>\r\n` data block on the wire
+fn data_block_len(bytes: usize) -> u64 {
+    (bytes as u64).saturating_add(2)
+}
+
+/// Reads a `<bytes>\r\n` data block. Returns `None` if it is malformed or not UTF-8.
+///
+/// `bytes` must already have been checked against the size limit, as the
+/// whole block is buffered.
+fn read_data_block<R: BufRead>(reader: &mut R, bytes: usize) -> io::Result<Option<String>> {
+    let mut data = Vec::new();
+    let expected = data_block_len(bytes);
+    if reader.take(expected).read_to_end(&mut data)? as u64 != expected {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if !data.ends_with(b"\r\n") {
+        return Ok(None);
+    }
+    data.truncate(bytes);
+    Ok(String::from_utf8(data).ok())
+}
+
+/// Discards the data block of a refused store command, so the connection
+/// stays in step with the client, without holding it in memory
+fn skip_data_block<R: BufRead>(reader: &mut R, bytes: usize) -> io::Result<()> {
+    io::copy(&mut reader.take(data_block_len(bytes)), &mut io::sink())?;
+    Ok(())
+}
+
+fn expiry(exptime: i64) -> Option<SystemTime> {
+    match exptime {
+        0 => None,
+        t if t < 0 => Some(UNIX_EPOCH),
+        t if t <= RELATIVE_EXPTIME_LIMIT => Some(SystemTime::now() + Duration::from_secs(t as u64)),
+        t => Some(UNIX_EPOCH + Duration::from_secs(t as u64)),
+    }
+}
+
+fn store<E: KvsEngine>(
+    engine: &E,
+    meta: &MetaTable,
+    command: &StoreCommand,
+    data: String,
+) -> Result<String> {
+    let lock = meta.lock(&command.key);
+    let existing = lock.lookup(engine)?;
+    let allowed = match (command.mode, existing) {
+        (StoreMode::Set, _) => Ok(()),
+        (StoreMode::Add, None) => Ok(()),
+        (StoreMode::Add, Some(_)) => Err("NOT_STORED"),
+        (StoreMode::Replace, Some(_)) => Ok(()),
+        (StoreMode::Replace, None) => Err("NOT_STORED"),
+        (StoreMode::Cas(_), None) => Err("NOT_FOUND"),
+        (StoreMode::Cas(unique), Some((_, item))) if item.cas == unique => Ok(()),
+        (StoreMode::Cas(_), Some(_)) => Err("EXISTS"),
+    };
+
+    match allowed {
+        Ok(()) => {
+            lock.store(engine, data, command.flags, expiry(command.exptime))?;
+            Ok("STORED\r\n".to_owned())
+        }
+        Err(reply) => Ok(format!("{}\r\n", reply)),
+    }
+}
+
+fn retrieve<E: KvsEngine>(
+    engine: &E,
+    meta: &MetaTable,
+    keys: &[&str],
+    with_cas: bool,
+) -> Result<String> {
+    if keys.is_empty() {
+        return Ok("ERROR\r\n".to_owned());
+    }
+    let mut reply = String::new();
+    for key in keys {
+        if let Some((value, item)) = meta.lock(key).lookup(engine)? {
+            if with_cas {
+                reply.push_str(&format!(
+                    "VALUE {} {} {} {}\r\n",
+                    key,
+                    item.flags,
+                    value.len(),
+                    item.cas
+                ));
+            } else {
+                reply.push_str(&format!("VALUE {} {} {}\r\n", key, item.flags, value.len()));
+            }
+            reply.push_str(&value);
+            reply.push_str("\r\n");
+        }
+    }
+    reply.push_str("END\r\n");
+    Ok(reply)
+}
+
+fn delete<E: KvsEngine>(engine: &E, meta: &MetaTable, args: &[&str]) -> Result<Option<String>> {
+    let (key, noreply) = match args {
+        [key] => (key, false),
+        [key, "noreply"] => (key, true),
+        _ => return Ok(Some("CLIENT_ERROR bad command line format\r\n".to_owned())),
+    };
+
+    let lock = meta.lock(key);
+    let reply = match lock.lookup(engine)? {
+        Some(_) => {
+            lock.delete(engine)?;
+            "DELETED\r\n"
+        }
+        None => "NOT_FOUND\r\n",
+    };
+    Ok(if noreply {
+        None
+    } else {
+        Some(reply.to_owned())
+    })
+}
+
+fn incr_decr<E: KvsEngine>(
+    engine: &E,
+    meta: &MetaTable,
+    args: &[&str],
+    incr: bool,
+) -> Result<Option<String>> {
+    let (key, delta, noreply) = match args {
+        [key, delta] => (key, delta, false),
+        [key, delta, "noreply"] => (key, delta, true),
+        _ => return Ok(Some("CLIENT_ERROR bad command line format\r\n".to_owned())),
+    };
+    let delta: u64 = match delta.parse() {
+        Ok(delta) => delta,
+        Err(_) => {
+            return Ok(Some(
+                "CLIENT_ERROR invalid numeric delta argument\r\n".to_owned(),
+            ))
+        }
+    };
+
+    let lock = meta.lock(key);
+    let reply = match lock.lookup(engine)? {
+        None => "NOT_FOUND\r\n".to_owned(),
+        Some((value, item)) => match value.trim_end().parse::<u64>() {
+            Ok(current) => {
+                let updated = if incr {
+                    current.wrapping_add(delta)
+                } else {
+                    current.saturating_sub(delta)
+                };
+                lock.store(engine, updated.to_string(), item.flags, item.expires_at)?;
+                format!("{}\r\n", updated)
+            }
+            Err(_) => "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n".to_owned(),
+        },
+    };
+    Ok(if noreply { None } else { Some(reply) })
+}
@@ -0,0 +1,293 @@
+//! Request and store metrics, exported in the Prometheus text format.
+//!
+//! `KvsServer` and `AsyncKvsServer` record into a shared `Metrics`;
+//! `MetricsExporter` serves it alongside the engine's `EngineStats` at
+//! `GET /metrics`.
+
+use crate::command::Response;
+use crate::engines::{EngineStats, KvsEngine};
+use crate::http::{self, HttpResponse};
+use crate::thread_pool::PoolStats;
+use crate::Result;
+use slog::{error, info};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+// Scrapes are bodiless `GET`s
+const MAX_BODY: usize = 4 * 1024;
+// Upper bounds of the latency histogram's buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    // Keyed by request type and outcome
+    requests: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+    // Keyed by request type
+    latency: Mutex<BTreeMap<&'static str, Histogram>>,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    /// Connections handed to the thread pool that no thread has picked up yet
+    pub(crate) queue_depth: AtomicUsize,
+    // Reads the stats of the pool serving connections, if there is one
+    pool: Mutex<Option<Box<dyn Fn() -> PoolStats + Send>>>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    // Not cumulative; one count per bucket plus one for anything slower
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Metrics {
+    /// Counts a request of the given `Request::kind` along with how it was
+    /// answered and how long that took
+    pub(crate) fn record(&self, kind: &'static str, response: &Response, elapsed: Duration) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((kind, outcome(response)))
+            .or_insert(0) += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        let mut latency = self.latency.lock().unwrap();
+        let histogram = latency.entry(kind).or_default();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        histogram.counts[bucket] += 1;
+        histogram.sum += seconds;
+    }
+
+    /// Exports the stats `pool` returns along with the rest
+    pub(crate) fn watch_pool(&self, pool: impl Fn() -> PoolStats + Send + 'static) {
+        *self.pool.lock().unwrap() = Some(Box::new(pool));
+    }
+
+    pub(crate) fn add_bytes_read(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_written(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Renders every metric, plus the engine's, in the Prometheus text format
+    pub fn render(&self, engine: &EngineStats) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "kvs_requests_total",
+            "counter",
+            "Requests answered, by type and outcome",
+        );
+        for ((kind, outcome), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "kvs_requests_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                kind, outcome, count
+            );
+        }
+
+        header(
+            &mut out,
+            "kvs_request_duration_seconds",
+            "histogram",
+            "Time from reading a request to having its response ready",
+        );
+        for (kind, histogram) in self.latency.lock().unwrap().iter() {
+            let mut cumulative = 0;
+            let bounds = LATENCY_BUCKETS.iter().map(|bound| bound.to_string());
+            for (bound, count) in bounds.chain(Some("+Inf".to_owned())).zip(&histogram.counts) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "kvs_request_duration_seconds_bucket{{type=\"{}\",le=\"{}\"}} {}",
+                    kind, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "kvs_request_duration_seconds_sum{{type=\"{}\"}} {}",
+                kind, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "kvs_request_duration_seconds_count{{type=\"{}\"}} {}",
+                kind, cumulative
+            );
+        }
+
+        let scalars: [(&str, &str, &str, String); 8] = [
+            (
+                "kvs_read_bytes_total",
+                "counter",
+                "Bytes of requests read",
+                self.bytes_read.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "kvs_written_bytes_total",
+                "counter",
+                "Bytes of responses and events written",
+                self.bytes_written.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "kvs_thread_pool_queue_depth",
+                "gauge",
+                "Connections waiting for a pool thread",
+                self.queue_depth.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "kvs_keys",
+                "gauge",
+                "Live keys in the store",
+                engine.keys.to_string(),
+            ),
+            (
+                "kvs_generations",
+                "gauge",
+                "Log files on disk",
+                engine.generations.to_string(),
+            ),
+            (
+                "kvs_dead_bytes",
+                "gauge",
+                "Bytes on disk taken up by overwritten and removed values",
+                engine.dead_bytes.to_string(),
+            ),
+            (
+                "kvs_compactions_total",
+                "counter",
+                "Compactions run",
+                engine.compactions.to_string(),
+            ),
+            (
+                "kvs_compaction_seconds_total",
+                "counter",
+                "Time spent compacting",
+                engine.compaction_seconds.to_string(),
+            ),
+        ];
+        for (name, kind, help, value) in scalars.iter() {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        if let Some(pool) = &*self.pool.lock().unwrap() {
+            let stats = pool();
+            header(
+                &mut out,
+                "kvs_thread_pool_workers",
+                "gauge",
+                "Pool threads, by whether they are running a job",
+            );
+            let _ = writeln!(
+                out,
+                "kvs_thread_pool_workers{{state=\"active\"}} {}",
+                stats.active_workers
+            );
+            let _ = writeln!(
+                out,
+                "kvs_thread_pool_workers{{state=\"idle\"}} {}",
+                stats.idle_workers
+            );
+            header(
+                &mut out,
+                "kvs_thread_pool_queued_jobs",
+                "gauge",
+                "Jobs waiting for a pool thread",
+            );
+            let _ = writeln!(out, "kvs_thread_pool_queued_jobs {}", stats.queued_jobs);
+            header(
+                &mut out,
+                "kvs_thread_pool_jobs_total",
+                "counter",
+                "Jobs the pool has run, by outcome",
+            );
+            let _ = writeln!(
+                out,
+                "kvs_thread_pool_jobs_total{{outcome=\"completed\"}} {}",
+                stats.completed_jobs
+            );
+            let _ = writeln!(
+                out,
+                "kvs_thread_pool_jobs_total{{outcome=\"panicked\"}} {}",
+                stats.panicked_jobs
+            );
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn outcome(response: &Response) -> &'static str {
+    match response {
+        Response::NotFound => "not_found",
+        Response::Error(_) => "error",
+        Response::Unauthorized(_) => "unauthorized",
+        Response::Rejected(_) => "rejected",
+        Response::Overloaded { .. } => "overloaded",
+        _ => "ok",
+    }
+}
+
+/// Serves `GET /metrics` over HTTP, one scrape at a time
+pub struct MetricsExporter<E: KvsEngine> {
+    engine: E,
+    metrics: Arc<Metrics>,
+    logger: slog::Logger,
+}
+
+impl<E: KvsEngine> MetricsExporter<E> {
+    pub fn new(engine: E, metrics: Arc<Metrics>, logger: slog::Logger) -> MetricsExporter<E> {
+        MetricsExporter {
+            engine,
+            metrics,
+            logger,
+        }
+    }
+
+    pub fn start<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        info!(self.logger, "starting metrics exporter...");
+        for stream in listener.incoming() {
+            let served = stream
+                .map_err(Into::into)
+                .and_then(|stream| self.serve(stream));
+            if let Err(e) = served {
+                error!(self.logger, "ERROR serving metrics: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn serve(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+        let request = http::read_request(&stream, MAX_BODY)?;
+        let segments: Vec<&str> = request.segments.iter().map(String::as_str).collect();
+        let response = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["metrics"]) => HttpResponse::text(
+                200,
+                "text/plain; version=0.0.4",
+                self.metrics.render(&self.engine.stats()?),
+            ),
+            (_, ["metrics"]) => HttpResponse::text(405, "text/plain", "method not allowed\n"),
+            _ => HttpResponse::text(404, "text/plain", "no such route\n"),
+        };
+        response.write_to(&stream)?;
+        Ok(())
+    }
+}
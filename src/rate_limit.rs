@@ -0,0 +1,98 @@
+//! Token-bucket rate limiting, keyed by client.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Past this many clients, buckets that have refilled are dropped. The next
+// prune waits until twice as many as survived are held, so each scan is paid
+// for by the buckets added since the last one.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Each client may make `burst` requests at once, refilling at `per_second`,
+/// which must be positive
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Whose bucket a request comes out of. Principals and peers have buckets
+/// of their own, even when a principal is named like an address. Unix socket
+/// peers have no address, so anonymous ones all share the `Ip("local")`
+/// bucket.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Client<'a> {
+    Principal(&'a str),
+    Ip(&'a str),
+}
+
+impl Client<'_> {
+    fn key(self) -> String {
+        match self {
+            Client::Principal(name) => format!("principal:{}", name),
+            Client::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+}
+
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    prune_at: usize,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                prune_at: PRUNE_THRESHOLD,
+            }),
+        }
+    }
+
+    /// Takes a token from `client`'s bucket, or returns how long until one is available
+    pub fn acquire(&self, client: Client) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { buckets, prune_at } = &mut *buckets;
+        if buckets.len() >= *prune_at {
+            let limit = self.limit;
+            buckets.retain(|_, bucket| bucket.refill(limit, now) < f64::from(limit.burst));
+            *prune_at = PRUNE_THRESHOLD.max(buckets.len() * 2);
+        }
+
+        let bucket = buckets.entry(client.key()).or_insert(Bucket {
+            tokens: f64::from(self.limit.burst),
+            updated: now,
+        });
+        bucket.tokens = bucket.refill(self.limit, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.limit.per_second,
+            ))
+        }
+    }
+}
+
+impl Bucket {
+    fn refill(&self, limit: RateLimit, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * limit.per_second).min(f64::from(limit.burst))
+    }
+}
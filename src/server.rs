@@ -1,115 +1,902 @@
-use crate::command;
+use crate::auth::{Acl, Session};
+use crate::command::{Admin, Request, Response, Watch};
+use crate::connections::ConnectionTable;
 use crate::engines::KvsEngine;
+use crate::error::Error;
+use crate::frame;
+use crate::logging::LogData;
+use crate::metrics::Metrics;
+use crate::rate_limit::{Client, RateLimit, RateLimiter};
+use crate::shutdown::{InFlight, ShutdownHandle};
+use crate::tls::ServerTlsConfig;
+use crate::transport::{Address, Listener, Stream};
 use crate::Result;
 use crate::ThreadPool;
-use slog::{error, info};
-use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use slog::{error, info, o, warn};
+use std::io::{self, Read};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Tunables for a `KvsServer`
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// How long shutdown waits for in-flight connections before giving up on them
+    pub drain_timeout: Duration,
+    /// Permission bits for Unix socket files, e.g. `0o660`. `None` leaves them to the umask.
+    pub unix_mode: Option<u32>,
+    /// Serve TLS on every listener
+    pub tls: Option<ServerTlsConfig>,
+    /// Require clients to authenticate, and check their requests against these grants
+    pub acl: Option<Acl>,
+    /// Run admin requests from anyone when there is no `acl`. Otherwise they
+    /// are refused unless the ACL gives the client admin access.
+    pub allow_admin_without_acl: bool,
+    pub limits: Limits,
+    /// Throttle each client, keyed by principal once authenticated and by IP before
+    pub rate_limit: Option<RateLimit>,
+    /// How much of each request's keys and values to log
+    pub log_data: LogData,
+}
+
+impl ServerConfig {
+    /// Names and describes each setting for `Admin::Config`, leaving out secrets
+    pub(crate) fn settings(&self) -> Vec<(String, String)> {
+        let off = || "off".to_owned();
+        let timeout = |timeout: Option<Duration>| timeout.map_or_else(off, |t| format!("{:?}", t));
+        let limit = |limit: Option<usize>| limit.map_or_else(off, |l| l.to_string());
+        let limits = &self.limits;
+        vec![
+            ("drain_timeout", format!("{:?}", self.drain_timeout)),
+            (
+                "unix_mode",
+                self.unix_mode
+                    .map_or_else(off, |mode| format!("{:o}", mode)),
+            ),
+            (
+                "tls_cert",
+                self.tls
+                    .as_ref()
+                    .map_or_else(off, |tls| tls.cert.display().to_string()),
+            ),
+            (
+                "tls_client_ca",
+                self.tls
+                    .as_ref()
+                    .and_then(|tls| tls.client_ca.as_ref())
+                    .map_or_else(off, |ca| ca.display().to_string()),
+            ),
+            (
+                "acl",
+                self.acl
+                    .as_ref()
+                    .map_or_else(off, |acl| format!("{} principals", acl.principals.len())),
+            ),
+            (
+                "allow_admin_without_acl",
+                self.allow_admin_without_acl.to_string(),
+            ),
+            ("read_timeout", timeout(limits.read_timeout)),
+            ("write_timeout", timeout(limits.write_timeout)),
+            ("idle_timeout", timeout(limits.idle_timeout)),
+            ("max_frame_size", limits.max_frame_size.to_string()),
+            ("max_key_size", limits.max_key_size.to_string()),
+            ("max_value_size", limits.max_value_size.to_string()),
+            ("max_connections", limit(limits.max_connections)),
+            ("max_backlog", limit(limits.max_backlog)),
+            (
+                "rate_limit",
+                self.rate_limit.map_or_else(off, |rate| {
+                    format!("{}/s, burst {}", rate.per_second, rate.burst)
+                }),
+            ),
+            ("log_data", format!("{:?}", self.log_data).to_lowercase()),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect()
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            drain_timeout: Duration::from_secs(10),
+            unix_mode: None,
+            tls: None,
+            acl: None,
+            allow_admin_without_acl: false,
+            limits: Limits::default(),
+            rate_limit: None,
+            log_data: LogData::default(),
+        }
+    }
+}
+
+/// Applies new limits, rate limits and grants to a running server.
+/// Connections pick them up at their next request, so none are dropped.
+///
+/// Handles are cheap to clone and can be moved to other threads, e.g. a
+/// signal handler.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    live: Arc<RwLock<Arc<Live>>>,
+    // Unset for servers that can't serve TLS or ACLs, which refuse reloads setting them
+    serves_acls: bool,
+}
+
+/// A server's settings as of the last reload
+pub(crate) struct Live {
+    pub config: ServerConfig,
+    pub acl: Option<Arc<Acl>>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Live {
+    fn new(config: ServerConfig) -> Live {
+        Live {
+            acl: config.acl.clone().map(Arc::new),
+            rate_limiter: config
+                .rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit))),
+            config,
+        }
+    }
+}
+
+impl ReloadHandle {
+    pub(crate) fn new(config: &ServerConfig) -> ReloadHandle {
+        ReloadHandle {
+            live: Arc::new(RwLock::new(Arc::new(Live::new(config.clone())))),
+            serves_acls: true,
+        }
+    }
+
+    /// A handle for a server without TLS or ACL support
+    pub(crate) fn without_acls(config: &ServerConfig) -> ReloadHandle {
+        ReloadHandle {
+            serves_acls: false,
+            ..ReloadHandle::new(config)
+        }
+    }
+
+    /// Swaps in `config`'s limits, rate limit and ACL. Its other settings only
+    /// take effect when the server is restarted.
+    ///
+    /// Authenticated connections keep their principal, with its new grants,
+    /// unless it was removed from the ACL or its credentials changed. Rate
+    /// limits start over.
+    ///
+    /// Servers that don't serve TLS or ACLs refuse a `config` that sets
+    /// either, keeping their old settings.
+    pub fn reload(&self, config: &ServerConfig) -> Result<()> {
+        if !self.serves_acls && (config.tls.is_some() || config.acl.is_some()) {
+            return Err(Error::Unsupported(
+                "the async server does not serve TLS or ACLs".to_owned(),
+            ));
+        }
+        let mut live = self.live.write().unwrap();
+        let config = ServerConfig {
+            acl: config.acl.clone(),
+            limits: config.limits.clone(),
+            rate_limit: config.rate_limit,
+            ..live.config.clone()
+        };
+        *live = Arc::new(Live::new(config));
+        Ok(())
+    }
+
+    pub(crate) fn current(&self) -> Arc<Live> {
+        self.live.read().unwrap().clone()
+    }
+}
+
+/// Bounds on how much time and memory a single client can take up.
+/// Timeouts of `None` wait forever.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// How long the rest of a request may take to arrive once it has started
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    /// How long a connection may wait between requests before it is closed
+    pub idle_timeout: Option<Duration>,
+    /// Largest request accepted, checked before the request is read into memory
+    pub max_frame_size: u32,
+    pub max_key_size: usize,
+    pub max_value_size: usize,
+    /// Connections beyond this many are answered with `Response::Rejected` and closed
+    pub max_connections: Option<usize>,
+    /// Connections waiting for a pool thread beyond this many are answered
+    /// with `Response::Overloaded` and closed
+    pub max_backlog: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            idle_timeout: Some(Duration::from_secs(60)),
+            max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+            max_key_size: 64 * 1024,
+            max_value_size: 8 * 1024 * 1024,
+            max_connections: Some(1024),
+            max_backlog: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Checks the sizes of a decoded request, returning the rejection to send if it is too large
+    pub(crate) fn check(&self, request: &Request) -> Option<Response> {
+        request
+            .keys()
+            .into_iter()
+            .find_map(|(key, value)| self.check_entry(key, value))
+    }
+
+    /// Checks the sizes of one key and the value it would be set to
+    pub(crate) fn check_entry(&self, key: &str, value: Option<&str>) -> Option<Response> {
+        if key.len() > self.max_key_size {
+            return Some(Response::Rejected(format!(
+                "key of {} bytes exceeds the {} byte limit",
+                key.len(),
+                self.max_key_size
+            )));
+        }
+        match value {
+            Some(value) if value.len() > self.max_value_size => Some(Response::Rejected(format!(
+                "value of {} bytes exceeds the {} byte limit",
+                value.len(),
+                self.max_value_size
+            ))),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn check_frame(&self, len: u32) -> Result<()> {
+        if len > self.max_frame_size {
+            return Err(Error::Rejected(format!(
+                "request of {} bytes exceeds the {} byte limit",
+                len, self.max_frame_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Connections over `max_connections` queue this deep for a rejection before being dropped outright
+pub(crate) const REJECT_BACKLOG: usize = 64;
+// What overloaded clients are told to wait when no better estimate is at hand
+pub(crate) const SHED_RETRY_AFTER: Duration = Duration::from_millis(100);
+// Rejected clients get this long to send their request and read the reply
+pub(crate) const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+// Quiet watches wake this often to notice shutdown and departed clients
+const WATCH_POLL: Duration = Duration::from_millis(250);
 
 pub struct KvsServer<E: KvsEngine, P: ThreadPool> {
     engine: E,
-    thread_pool: P,
+    // Shared with `metrics`, which reads its stats
+    thread_pool: Arc<P>,
     logger: slog::Logger,
+    config: ServerConfig,
+    shutdown: ShutdownHandle,
+    in_flight: InFlight,
+    metrics: Arc<Metrics>,
+    reload: ReloadHandle,
 }
 
 impl<E: KvsEngine, P: ThreadPool> KvsServer<E, P> {
     pub fn new(engine: E, thread_pool: P, logger: slog::Logger) -> KvsServer<E, P> {
+        KvsServer::with_config(engine, thread_pool, logger, ServerConfig::default())
+    }
+
+    pub fn with_config(
+        engine: E,
+        thread_pool: P,
+        logger: slog::Logger,
+        config: ServerConfig,
+    ) -> KvsServer<E, P> {
+        let thread_pool = Arc::new(thread_pool);
+        let metrics = Arc::new(Metrics::default());
+        let pool = Arc::clone(&thread_pool);
+        metrics.watch_pool(move || pool.stats());
         KvsServer {
             engine,
             thread_pool,
             logger,
+            reload: ReloadHandle::new(&config),
+            config,
+            shutdown: ShutdownHandle::new(),
+            in_flight: InFlight::default(),
+            metrics,
         }
     }
 
+    /// What the server records about the requests it serves, for `MetricsExporter`
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Returns a handle that makes `start` stop accepting, drain and return
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Returns a handle that changes the server's limits and grants while it runs
+    pub fn reload_handle(&self) -> ReloadHandle {
+        self.reload.clone()
+    }
+
+    /// Serves TCP connections on `addr` until shutdown is requested through a `ShutdownHandle`.
+    ///
+    /// On shutdown, connections already accepted get up to `drain_timeout` to
+    /// finish, then the engine is flushed before returning.
     pub fn start<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
-        let connection = TcpListener::bind(addr)?;
-        info!(self.logger, "starting server...");
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+        self.serve_on(vec![listener])
+    }
+
+    /// Like `start`, but listens on several addresses at once, e.g. TCP and a Unix socket
+    pub fn start_on(&mut self, addresses: &[Address]) -> Result<()> {
+        let listeners = addresses
+            .iter()
+            .map(|address| Listener::bind(address, self.config.unix_mode))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.serve_on(listeners)
+    }
+
+    fn serve_on(&mut self, listeners: Vec<Listener>) -> Result<()> {
+        let tls = self
+            .config
+            .tls
+            .as_ref()
+            .map(ServerTlsConfig::load)
+            .transpose()?;
+        let control = Arc::new(Control {
+            reload: self.reload.clone(),
+            connections: ConnectionTable::default(),
+            shutdown: self.shutdown.clone(),
+            started: Instant::now(),
+            metrics: self.metrics.clone(),
+            log_data: self.config.log_data,
+            request_ids: AtomicU64::new(0),
+        });
+
+        // Each listener accepts on its own thread and hands streams to this one
+        let (stream_tx, stream_rx) = mpsc::channel();
+        let mut acceptors = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            let address = listener.local_address()?;
+            self.shutdown.register_listener(address.clone());
+            info!(self.logger, "starting server..."; "listener" => address.to_string());
+
+            let stream_tx = stream_tx.clone();
+            let shutdown = self.shutdown.clone();
+            acceptors.push(thread::spawn(move || loop {
+                let stream = listener.accept();
+                if shutdown.is_shutdown() || stream_tx.send(stream).is_err() {
+                    break;
+                }
+            }));
+        }
+        drop(stream_tx);
+
+        // Connections over the limits are turned away on one thread, so a flood
+        // of them cannot tie up the pool
+        let (reject_tx, reject_rx) = mpsc::sync_channel::<(Stream, Response)>(REJECT_BACKLOG);
+        let rejector = {
+            let tls = tls.clone();
+            let reload = self.reload.clone();
+            let logger = self.logger.clone();
+            thread::spawn(move || {
+                for (stream, response) in reject_rx {
+                    let limits = &reload.current().config.limits;
+                    if let Err(e) = reject(stream, response, tls.clone(), limits) {
+                        info!(logger, "failed to send rejection: {}", e);
+                    }
+                }
+            })
+        };
+
+        for stream in stream_rx {
+            let live = self.reload.current();
+            let limits = &live.config.limits;
+            let refusal = match (limits.max_connections, limits.max_backlog) {
+                (Some(max), _) if self.in_flight.count() >= max => {
+                    warn!(self.logger, "too many connections, rejecting"; "max_connections" => max);
+                    Some(Response::Rejected("too many connections".to_owned()))
+                }
+                (_, Some(max)) if self.metrics.queue_depth.load(Ordering::SeqCst) >= max => {
+                    warn!(self.logger, "backlog full, shedding load"; "max_backlog" => max);
+                    Some(overloaded(SHED_RETRY_AFTER))
+                }
+                _ => None,
+            };
+            if let Some(response) = refusal {
+                if let Ok(stream) = stream {
+                    let _ = reject_tx.try_send((stream, response));
+                }
+                continue;
+            }
 
-        for stream in connection.incoming() {
             let engine = self.engine.clone();
             let logger = self.logger.clone();
-            self.thread_pool.spawn(move || match stream {
-                Ok(stream) => {
-                    serve(engine, stream, logger);
+            let tls = tls.clone();
+            let gate = Gate::new(self.reload.clone());
+            let control = control.clone();
+            let in_flight = self.in_flight.enter();
+            let queued = Queued::new(stream, self.metrics.clone(), reject_tx.clone());
+            let spawned = self.thread_pool.try_spawn(move || {
+                let stream = queued.start();
+                let stream = stream.map_err(Error::from).and_then(|stream| match tls {
+                    Some(tls) => stream.accept_tls(tls),
+                    None => Ok(stream),
+                });
+                match stream {
+                    Ok(stream) => {
+                        serve(engine, stream, gate, &control, logger);
+                    }
+                    Err(stream_err) => {
+                        error!(logger, "ERROR connecting to stream: {}", stream_err)
+                    }
                 }
-                Err(stream_err) => error!(logger, "ERROR connecting to stream: {}", stream_err),
+                drop(in_flight);
             });
+            if let Err(e) = spawned {
+                warn!(self.logger, "thread pool full, shedding load: {}", e);
+            }
         }
+        for acceptor in acceptors {
+            let _ = acceptor.join();
+        }
+        drop(reject_tx);
+        let _ = rejector.join();
+
+        info!(self.logger, "shutting down, draining connections...");
+        // A timeout too long to represent has no deadline
+        let deadline = Instant::now().checked_add(self.config.drain_timeout);
+        let abandoned = self.in_flight.drain(self.config.drain_timeout);
+        if abandoned > 0 {
+            warn!(self.logger, "drain timed out"; "abandoned" => abandoned);
+        }
+        // Abandoned connections keep their threads; the rest are let go
+        self.thread_pool
+            .shutdown(deadline.map_or(Duration::MAX, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            }));
+        self.engine.flush()?;
+        info!(self.logger, "shutdown complete");
         Ok(())
     }
 }
 
-fn serve<E: KvsEngine>(engine: E, stream: TcpStream, logger: slog::Logger) {
+/// A connection waiting in the thread pool's queue. If its job is dropped
+/// without running, e.g. turned away by a full queue, the client is told the
+/// server is overloaded rather than just hung up on.
+struct Queued {
+    stream: Option<io::Result<Stream>>,
+    metrics: Arc<Metrics>,
+    reject_tx: mpsc::SyncSender<(Stream, Response)>,
+}
+
+impl Queued {
+    fn new(
+        stream: io::Result<Stream>,
+        metrics: Arc<Metrics>,
+        reject_tx: mpsc::SyncSender<(Stream, Response)>,
+    ) -> Queued {
+        metrics.queue_depth.fetch_add(1, Ordering::SeqCst);
+        Queued {
+            stream: Some(stream),
+            metrics,
+            reject_tx,
+        }
+    }
+
+    /// Takes the connection off the queue for a thread to serve
+    fn start(mut self) -> io::Result<Stream> {
+        self.stream.take().unwrap()
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.metrics.queue_depth.fetch_sub(1, Ordering::SeqCst);
+        if let Some(Ok(stream)) = self.stream.take() {
+            let _ = self
+                .reject_tx
+                .try_send((stream, overloaded(SHED_RETRY_AFTER)));
+        }
+    }
+}
+
+/// Server-wide state shared by every connection, much of it for admin requests
+struct Control {
+    reload: ReloadHandle,
+    connections: ConnectionTable,
+    shutdown: ShutdownHandle,
+    started: Instant,
+    metrics: Arc<Metrics>,
+    log_data: LogData,
+    request_ids: AtomicU64,
+}
+
+/// What a connection's requests are checked against before they reach the engine
+struct Gate {
+    reload: ReloadHandle,
+    live: Arc<Live>,
+    session: Option<Session>,
+}
+
+impl Gate {
+    fn new(reload: ReloadHandle) -> Gate {
+        let live = reload.current();
+        Gate {
+            session: live.acl.clone().map(Session::new),
+            reload,
+            live,
+        }
+    }
+
+    /// Picks up settings reloaded since the last call
+    fn refresh(&mut self) {
+        let live = self.reload.current();
+        if Arc::ptr_eq(&live, &self.live) {
+            return;
+        }
+        self.session = match (live.acl.clone(), self.session.take()) {
+            (Some(acl), Some(mut session)) => {
+                session.reload(acl);
+                Some(session)
+            }
+            (Some(acl), None) => Some(Session::new(acl)),
+            (None, _) => None,
+        };
+        self.live = live;
+    }
+
+    fn limits(&self) -> &Limits {
+        &self.live.config.limits
+    }
+
+    fn principal(&self) -> Option<&str> {
+        self.session.as_ref().and_then(Session::principal)
+    }
+
+    /// Returns the response to send instead of running `request`, if it may not run
+    fn check(&mut self, request: &Request, peer: &str) -> Option<Response> {
+        if let Some(rejection) = self.limits().check(request) {
+            return Some(rejection);
+        }
+        if let Some(rate_limiter) = &self.live.rate_limiter {
+            let client = match self.principal() {
+                Some(principal) => Client::Principal(principal),
+                None => Client::Ip(peer),
+            };
+            if let Err(retry_after) = rate_limiter.acquire(client) {
+                return Some(overloaded(retry_after));
+            }
+        }
+        match self.session.as_mut() {
+            Some(session) => session.check(request),
+            // Admin requests act on the whole server, so anonymous clients
+            // only get them when the operator asked for that
+            None if matches!(request, Request::Admin(_))
+                && !self.live.config.allow_admin_without_acl =>
+            {
+                Some(Response::Unauthorized(
+                    "admin requests need an ACL granting admin access".to_owned(),
+                ))
+            }
+            None => None,
+        }
+    }
+}
+
+pub(crate) fn overloaded(retry_after: Duration) -> Response {
+    Response::Overloaded {
+        retry_after_ms: retry_after.as_millis() as u64,
+    }
+}
+
+/// Serves requests until the client closes the connection, checking each
+/// against the connection's `Gate` first. A watch takes over the connection
+/// until the client leaves or the server shuts down.
+fn serve<E: KvsEngine>(
+    engine: E,
+    mut stream: Stream,
+    mut gate: Gate,
+    control: &Control,
+    logger: slog::Logger,
+) {
+    let peer = stream
+        .peer_ip()
+        .map_or_else(|| "local".to_owned(), |ip| ip.to_string());
+    let record = control.connections.open(peer.clone());
+    let logger = logger.new(o!("conn" => record.id(), "peer" => peer.clone()));
     info!(logger, "accepting incoming connection...");
 
-    match bincode::deserialize_from(&stream) {
-        Ok(command::Request::Get(key)) => {
-            info!(logger, "GET request"; "key" => key.as_str());
-            match engine.get(key) {
-                Ok(value) => {
-                    if let Some(v) = value {
-                        if let Err(e) = bincode::serialize_into(stream, &command::Response::OK(v)) {
-                            error!(logger, "ERROR serialzing response: {}", e)
-                        }
-                    } else {
-                        if let Err(e) =
-                            bincode::serialize_into(stream, &command::Response::NotFound)
-                        {
-                            error!(logger, "ERROR serialzing response: {}", e)
-                        }
-                    };
+    loop {
+        gate.refresh();
+        if let Err(e) = stream.set_write_timeout(gate.limits().write_timeout) {
+            error!(logger, "ERROR configuring stream: {}", e);
+            break;
+        }
+        let request = match read_request(&mut stream, gate.limits()) {
+            Ok(Some((request, len))) => {
+                control.metrics.add_bytes_read(len);
+                request
+            }
+            Ok(None) => break,
+            Err(Error::Rejected(reason)) => {
+                warn!(logger, "rejected request"; "reason" => reason.as_str());
+                let _ = frame::write_frame(&mut stream, &Response::Rejected(reason));
+                break;
+            }
+            Err(Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                info!(logger, "closing connection after timeout");
+                break;
+            }
+            Err(e) => {
+                error!(logger, "ERROR deserializing request: {}", e);
+                break;
+            }
+        };
+        let started = Instant::now();
+        let kind = request.kind();
+        let request_id = control.request_ids.fetch_add(1, Ordering::Relaxed) + 1;
+        let logger = logger.new(o!("request_id" => request_id));
+        // Settings may have been reloaded while the connection sat idle
+        gate.refresh();
+        let refused = gate.check(&request, &peer);
+        match &refused {
+            Some(Response::Rejected(reason)) | Some(Response::Unauthorized(reason)) => {
+                warn!(logger, "refused request"; "reason" => reason.as_str())
+            }
+            Some(Response::Overloaded { .. }) => warn!(logger, "client over its rate limit"),
+            _ => {}
+        }
+        record.served(gate.principal());
+        let shutting_down = refused.is_none() && matches!(request, Request::Admin(Admin::Shutdown));
+        let response = match (refused, request) {
+            (None, Request::Watch(watch)) => {
+                if let Err(e) = stream_events(&engine, &mut stream, watch, control, &logger) {
+                    info!(logger, "watch ended: {}", e);
+                }
+                break;
+            }
+            (None, Request::Admin(admin)) => handle_admin(&engine, admin, control, &logger),
+            (Some(refusal), _) => refusal,
+            (None, request) => handle_request(&engine, request, control.log_data, &logger),
+        };
+        control.metrics.record(kind, &response, started.elapsed());
+        match frame::write_frame(&mut stream, &response) {
+            Ok(written) => control.metrics.add_bytes_written(written),
+            Err(e) => {
+                error!(logger, "ERROR serialzing response: {}", e);
+                break;
+            }
+        }
+        if shutting_down {
+            control.shutdown.shutdown();
+            break;
+        }
+    }
+}
+
+fn handle_admin<E: KvsEngine>(
+    engine: &E,
+    admin: Admin,
+    control: &Control,
+    logger: &slog::Logger,
+) -> Response {
+    warn!(logger, "ADMIN request"; "command" => format!("{:?}", admin));
+    let result = match admin {
+        Admin::Compact => engine.compact().map(|_| Response::OK("".to_string())),
+        Admin::Flush => engine.flush().map(|_| Response::OK("".to_string())),
+        Admin::Stats => engine.stats().map(|stats| {
+            Response::Entries(vec![
+                ("keys".to_owned(), stats.keys.to_string()),
+                ("generations".to_owned(), stats.generations.to_string()),
+                ("dead_bytes".to_owned(), stats.dead_bytes.to_string()),
+                ("compactions".to_owned(), stats.compactions.to_string()),
+                (
+                    "connections".to_owned(),
+                    control.connections.len().to_string(),
+                ),
+                (
+                    "uptime_secs".to_owned(),
+                    control.started.elapsed().as_secs().to_string(),
+                ),
+            ])
+        }),
+        Admin::Config => Ok(Response::Entries(
+            control.reload.current().config.settings(),
+        )),
+        Admin::Connections => Ok(Response::Connections(control.connections.list())),
+        // The caller shuts down once the reply is on its way
+        Admin::Shutdown => Ok(Response::OK("".to_string())),
+    };
+    result.unwrap_or_else(|e| {
+        error!(logger, "ERROR running admin command: {}", e);
+        Response::Error(format!("Error ADMIN {:?}", admin))
+    })
+}
+
+/// Acknowledges `watch`, then sends an event for each matching write until
+/// the client closes the connection, the server shuts down or the client
+/// falls too far behind, which ends the watch with `Response::Error`
+fn stream_events<E: KvsEngine>(
+    engine: &E,
+    stream: &mut Stream,
+    watch: Watch,
+    control: &Control,
+    logger: &slog::Logger,
+) -> Result<()> {
+    info!(logger, "WATCH request"; "prefix" => control.log_data.key(watch.prefix()));
+    let started = Instant::now();
+    let metrics = &control.metrics;
+    let (watcher, ack) = match engine.watch(watch.prefix().to_owned()) {
+        Ok(watcher) => (Some(watcher), Response::OK("".to_string())),
+        Err(e) => {
+            error!(logger, "ERROR watching keys: {}", e);
+            (None, Response::Error("Error WATCH".to_string()))
+        }
+    };
+    metrics.record("watch", &ack, started.elapsed());
+    metrics.add_bytes_written(frame::write_frame(&mut *stream, &ack)?);
+    let watcher = match watcher {
+        Some(watcher) => watcher,
+        None => return Ok(()),
+    };
+
+    // Clients send nothing more once watching, so a read only returns when they leave
+    stream.set_read_timeout(Some(Duration::from_millis(1)))?;
+    while !control.shutdown.is_shutdown() {
+        match watcher.recv_timeout(WATCH_POLL) {
+            Ok(event) => {
+                if watch.matches(&event.key) {
+                    let written = frame::write_frame(&mut *stream, &Response::Event(event))?;
+                    metrics.add_bytes_written(written);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => match stream.read(&mut [0; 1]) {
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                _ => break,
+            },
+            Err(RecvTimeoutError::Disconnected) => {
+                if watcher.overflowed() {
+                    warn!(logger, "watcher fell behind, ending the watch");
+                    let response = Response::Error("Error WATCH fell behind".to_string());
+                    metrics.add_bytes_written(frame::write_frame(&mut *stream, &response)?);
                 }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads one request and its size in bytes, allowing `idle_timeout` for it
+/// to start and `read_timeout` for the rest
+fn read_request(stream: &mut Stream, limits: &Limits) -> Result<Option<(Request, u64)>> {
+    stream.set_read_timeout(limits.idle_timeout)?;
+    let len = match frame::read_header(&mut *stream)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+    limits.check_frame(len)?;
+    stream.set_read_timeout(limits.read_timeout)?;
+    let request = frame::read_body(stream, len)?;
+    Ok(Some((request, frame::HEADER_LEN + u64::from(len))))
+}
+
+/// Reads the client's request, so closing does not reset the connection
+/// under it, then replies with `response` instead of serving it
+fn reject(
+    stream: Stream,
+    response: Response,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    limits: &Limits,
+) -> Result<()> {
+    let mut stream = match tls {
+        Some(tls) => stream.accept_tls(tls)?,
+        None => stream,
+    };
+    stream.set_read_timeout(Some(REJECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(REJECT_TIMEOUT))?;
+    if let Some(len) = frame::read_header(&mut stream)? {
+        limits.check_frame(len)?;
+        frame::read_body::<Request, _>(&mut stream, len)?;
+    }
+    frame::write_frame(&mut stream, &response)?;
+    Ok(())
+}
+
+/// Runs a single request against the engine. Shared by the blocking and async servers.
+pub(crate) fn handle_request<E: KvsEngine>(
+    engine: &E,
+    request: Request,
+    log_data: LogData,
+    logger: &slog::Logger,
+) -> Response {
+    match request {
+        Request::Get(key) => {
+            info!(logger, "GET request"; "key" => log_data.key(&key));
+            match engine.get(key) {
+                Ok(Some(value)) => Response::OK(value),
+                Ok(None) => Response::NotFound,
                 Err(e) => {
                     error!(logger, "ERROR requesting key: {}", e);
-                    if let Err(e) = bincode::serialize_into(
-                        stream,
-                        &command::Response::Error("Error GET key".to_string()),
-                    ) {
-                        error!(logger, "ERROR serialzing response: {}", e)
-                    };
+                    Response::Error("Error GET key".to_string())
                 }
             }
         }
-        Ok(command::Request::Set(key, value)) => {
-            info!(logger, "SET request"; "key" => key.as_str(), "value" => value.as_str());
+        Request::Set(key, value) => {
+            info!(logger, "SET request"; "key" => log_data.key(&key), "value" => log_data.value(&value));
             match engine.set(key, value) {
-                Ok(_) => {
-                    if let Err(e) =
-                        bincode::serialize_into(stream, &command::Response::OK("".to_string()))
-                    {
-                        error!(logger, "ERROR serialzing response: {}", e)
-                    }
-                }
+                Ok(_) => Response::OK("".to_string()),
                 Err(e) => {
                     error!(logger, "ERROR requesting value: {}", e);
-                    if let Err(e) = bincode::serialize_into(
-                        stream,
-                        &command::Response::Error("Error SET key".to_string()),
-                    ) {
-                        error!(logger, "ERROR serialzing response: {}", e)
-                    }
+                    Response::Error("Error SET key".to_string())
                 }
             }
         }
-        Ok(command::Request::Rm(key)) => {
-            info!(logger, "RM request"; "key" => key.as_str());
+        Request::Rm(key) => {
+            info!(logger, "RM request"; "key" => log_data.key(&key));
             match engine.remove(key) {
-                Ok(_) => {
-                    if let Err(e) =
-                        bincode::serialize_into(stream, &command::Response::OK("".to_string()))
-                    {
-                        error!(logger, "ERROR serialzing response: {}", e)
-                    }
-                }
+                Ok(_) => Response::OK("".to_string()),
                 Err(e) => {
                     error!(logger, "ERROR removing key: {}", e);
-                    if let Err(e) = bincode::serialize_into(stream, &command::Response::NotFound) {
-                        error!(logger, "ERROR serialzing response: {}", e)
-                    };
+                    Response::NotFound
                 }
             }
         }
-        Err(e) => {
-            error!(logger, "ERROR deserializing request: {}", e);
+        // Multi-key requests run key by key; a failure on one key does not stop the rest
+        Request::MultiGet(keys) => {
+            info!(logger, "MGET request"; "keys" => keys.len());
+            Response::Multi(
+                keys.into_iter()
+                    .map(|key| handle_request(engine, Request::Get(key), log_data, logger))
+                    .collect(),
+            )
+        }
+        Request::MultiSet(pairs) => {
+            info!(logger, "MSET request"; "keys" => pairs.len());
+            Response::Multi(
+                pairs
+                    .into_iter()
+                    .map(|(key, value)| {
+                        handle_request(engine, Request::Set(key, value), log_data, logger)
+                    })
+                    .collect(),
+            )
+        }
+        Request::MultiRemove(keys) => {
+            info!(logger, "MRM request"; "keys" => keys.len());
+            Response::Multi(
+                keys.into_iter()
+                    .map(|key| handle_request(engine, Request::Rm(key), log_data, logger))
+                    .collect(),
+            )
         }
+        // Without an ACL every client is trusted, so any credentials are accepted
+        Request::Auth(_) => Response::OK("".to_string()),
+        // Watches need a connection of their own, which only `KvsServer` gives them
+        Request::Watch(_) => Response::Error("WATCH is not supported here".to_string()),
+        Request::Admin(_) => Response::Error("ADMIN is not supported here".to_string()),
     }
 }
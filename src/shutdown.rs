@@ -0,0 +1,85 @@
+use crate::transport::{Address, Stream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Asks a running server to stop accepting connections and wind down.
+///
+/// Handles are cheap to clone and can be moved to other threads, e.g. a
+/// signal handler.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle(Arc<ShutdownState>);
+
+#[derive(Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    // Listeners blocked in `accept` are woken by connecting to them
+    listeners: Mutex<Vec<Address>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle::default()
+    }
+
+    pub fn shutdown(&self) {
+        self.0.requested.store(true, Ordering::SeqCst);
+        for address in self.0.listeners.lock().unwrap().iter() {
+            let _ = Stream::connect(address);
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.0.requested.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn register_listener(&self, address: Address) {
+        self.0.listeners.lock().unwrap().push(address);
+    }
+}
+
+/// Counts connections that have been accepted but not yet fully served
+#[derive(Clone, Default)]
+pub(crate) struct InFlight(Arc<(Mutex<usize>, Condvar)>);
+
+pub(crate) struct InFlightGuard(InFlight);
+
+impl InFlight {
+    pub fn enter(&self) -> InFlightGuard {
+        *(self.0).0.lock().unwrap() += 1;
+        InFlightGuard(self.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        *(self.0).0.lock().unwrap()
+    }
+
+    /// Waits until nothing is in flight or the timeout passes, returning how
+    /// many are left. A timeout too long to represent waits forever.
+    pub fn drain(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now().checked_add(timeout);
+        let (count, cvar) = &*self.0;
+        let mut count = count.lock().unwrap();
+        while *count > 0 {
+            count = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    cvar.wait_timeout(count, deadline - now).unwrap().0
+                }
+                None => cvar.wait(count).unwrap(),
+            };
+        }
+        *count
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let (count, cvar) = &*(self.0).0;
+        *count.lock().unwrap() -= 1;
+        cvar.notify_all();
+    }
+}
@@ -0,0 +1,136 @@
+use super::panic_message;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use thiserror::Error;
+
+/// Why a job spawned with `spawn_with_handle` gave no result
+#[derive(Error, Debug)]
+pub enum JobError {
+    /// The job panicked with this payload
+    #[error("Job panicked: {}", panic_message(&**.0))]
+    Panicked(Box<dyn Any + Send>),
+    /// `JobHandle::cancel` stopped the job before it started
+    #[error("Job cancelled")]
+    Cancelled,
+    /// The pool dropped the job without running it, e.g. to make room in a
+    /// full queue or because it was shut down
+    #[error("Job dropped by the pool")]
+    Dropped,
+}
+
+enum Slot<T> {
+    Pending,
+    Running,
+    Done(Result<T, JobError>),
+    // The outcome has been taken by `join`
+    Joined,
+}
+
+struct Shared<T> {
+    slot: Mutex<Slot<T>>,
+    done: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn finish(&self, outcome: Result<T, JobError>) {
+        *self.slot.lock().unwrap() = Slot::Done(outcome);
+        self.done.notify_all();
+    }
+}
+
+/// Waits for, or cancels, a job spawned with `spawn_with_handle`
+pub struct JobHandle<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Waits for the job to finish, returning what it returned, or why it
+    /// did not
+    pub fn join(self) -> Result<T, JobError> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        loop {
+            match std::mem::replace(&mut *slot, Slot::Joined) {
+                Slot::Done(outcome) => return outcome,
+                pending => *slot = pending,
+            }
+            slot = self.shared.done.wait(slot).unwrap();
+        }
+    }
+
+    /// Stops the job from running if no thread has started it yet, returning
+    /// whether it did. The job still passes through the pool's queue, doing
+    /// nothing when its turn comes.
+    pub fn cancel(&self) -> bool {
+        let mut slot = self.shared.slot.lock().unwrap();
+        if let Slot::Pending = *slot {
+            *slot = Slot::Done(Err(JobError::Cancelled));
+            drop(slot);
+            self.shared.done.notify_all();
+            return true;
+        }
+        false
+    }
+
+    /// Whether `join` would return without waiting
+    pub fn is_finished(&self) -> bool {
+        matches!(*self.shared.slot.lock().unwrap(), Slot::Done(_))
+    }
+}
+
+// Held by the job, so one the pool drops without running still finishes its handle
+struct Completer<T>(Arc<Shared<T>>);
+
+impl<T> Completer<T> {
+    /// Marks the job as started, unless it was cancelled
+    fn start(&self) -> bool {
+        let mut slot = self.0.slot.lock().unwrap();
+        match *slot {
+            Slot::Pending => {
+                *slot = Slot::Running;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let mut slot = self.0.slot.lock().unwrap();
+        if let Slot::Pending = *slot {
+            *slot = Slot::Done(Err(JobError::Dropped));
+            drop(slot);
+            self.0.done.notify_all();
+        }
+    }
+}
+
+/// Wraps `job` to report to the returned handle. A panic is caught for the
+/// handle, then raised again with just its message so the pool still counts,
+/// logs and handles it.
+pub(super) fn with_handle<F, T>(job: F) -> (JobHandle<T>, impl FnOnce() + Send + 'static)
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let shared = Arc::new(Shared {
+        slot: Mutex::new(Slot::Pending),
+        done: Condvar::new(),
+    });
+    let completer = Completer(Arc::clone(&shared));
+    let job = move || {
+        if !completer.start() {
+            return;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(job)) {
+            Ok(value) => completer.0.finish(Ok(value)),
+            Err(payload) => {
+                let message = panic_message(&*payload).to_owned();
+                completer.0.finish(Err(JobError::Panicked(payload)));
+                panic::resume_unwind(Box::new(message));
+            }
+        }
+    };
+    (JobHandle { shared }, job)
+}
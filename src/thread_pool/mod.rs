@@ -1,21 +1,302 @@
-use crate::Result;
+use crate::shutdown::InFlight;
+use crate::{Error, Result};
+use slog::error;
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub trait ThreadPool {
+pub trait ThreadPool: Send + Sync + 'static {
+    /// Creates a pool of `threads` threads with the default `PoolConfig`
     fn new(threads: u32) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::with_config(threads, PoolConfig::default())
+    }
+
+    fn with_config(threads: u32, config: PoolConfig) -> Result<Self>
     where
         Self: Sized;
 
     fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static;
+
+    /// Like `spawn`, but fails with `Error::QueueFull` if a bounded pool has
+    /// no room for the job, or `Error::PoolShutDown` once the pool is shut
+    /// down. Either way the job is dropped without running.
+    ///
+    /// Pools without a bounded queue always take the job.
+    fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawn(job);
+        Ok(())
+    }
+
+    /// Stops taking jobs, then waits up to `timeout` for the ones already
+    /// spawned to finish and the pool's threads to exit. Returns how many jobs
+    /// were abandoned, still queued or running when the timeout passed.
+    ///
+    /// Jobs spawned after shutdown are dropped without running.
+    fn shutdown(&self, timeout: Duration) -> usize;
+
+    /// Like `try_spawn`, but returns a handle to wait for the job's result,
+    /// or its panic, and to cancel it before it starts
+    fn spawn_with_handle<F, T>(&self, job: F) -> Result<JobHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (handle, job) = handle::with_handle(job);
+        self.try_spawn(job)?;
+        Ok(handle)
+    }
+
+    /// Changes the pool to `threads` threads, adding them at once and letting
+    /// extra ones go as they finish their jobs. Fails with
+    /// `Error::Unsupported` for pools that cannot change size.
+    fn resize(&self, _threads: u32) -> Result<()> {
+        Err(Error::Unsupported(
+            "this thread pool cannot be resized".to_owned(),
+        ))
+    }
+
+    /// Like `shutdown`, but waits as long as the jobs take
+    fn join(&self) {
+        self.shutdown(Duration::MAX);
+    }
+
+    /// What the pool's threads are up to and how its jobs have fared
+    fn stats(&self) -> PoolStats;
+}
+
+/// Called with the payload of each job that panics
+pub type PanicHandler = Arc<dyn Fn(&(dyn Any + Send)) + Send + Sync>;
+
+/// Tunables shared by every `ThreadPool`
+#[derive(Clone)]
+pub struct PoolConfig {
+    /// Where panicking jobs are logged
+    pub logger: slog::Logger,
+    /// Called after a panicking job is logged, e.g. to count or report it
+    pub panic_handler: Option<PanicHandler>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            logger: slog::Logger::root(slog::Discard, slog::o!()),
+            panic_handler: None,
+        }
+    }
+}
+
+impl fmt::Debug for PoolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PoolConfig")
+            .field("panic_handler", &self.panic_handler.is_some())
+            .finish()
+    }
+}
+
+/// A snapshot of a pool's threads and jobs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Threads running a job
+    pub active_workers: usize,
+    /// Threads waiting for a job
+    pub idle_workers: usize,
+    /// Jobs spawned that no thread has picked up yet
+    pub queued_jobs: usize,
+    /// Jobs that ran to completion
+    pub completed_jobs: u64,
+    /// Jobs that panicked
+    pub panicked_jobs: u64,
+}
+
+impl PoolStats {
+    /// Threads in the pool, busy or not
+    pub fn workers(&self) -> usize {
+        self.active_workers + self.idle_workers
+    }
+}
+
+/// What a bounded pool does with a job that arrives while its queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Waits for room in the queue
+    Block,
+    /// Turns the new job away
+    Reject,
+    /// Drops the job that has waited longest to make room for the new one
+    DropOldest,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<OverflowPolicy, String> {
+        match s {
+            "block" => Ok(OverflowPolicy::Block),
+            "reject" => Ok(OverflowPolicy::Reject),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            _ => Err(format!("{} is not one of block, reject or drop-oldest", s)),
+        }
+    }
+}
+
+/// Counts a pool's jobs from `spawn` until they finish, and turns new ones
+/// away once the pool is shut down.
+///
+/// Every job a pool runs goes through `wrap`, which keeps the pool's stats
+/// and catches the job's panic so the thread running it carries on.
+struct Jobs {
+    closed: AtomicBool,
+    in_flight: InFlight,
+    tracker: Arc<Tracker>,
+}
+
+#[derive(Default)]
+struct Tracker {
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    config: PoolConfig,
+}
+
+impl Jobs {
+    fn new(config: PoolConfig) -> Jobs {
+        Jobs {
+            closed: AtomicBool::new(false),
+            in_flight: InFlight::default(),
+            tracker: Arc::new(Tracker {
+                config,
+                ..Tracker::default()
+            }),
+        }
+    }
+
+    /// Returns `job` ready for a thread to run, or `Error::PoolShutDown` once
+    /// the pool is shut down. The job counts as queued until it starts, or
+    /// until it is dropped without running.
+    fn wrap<F>(&self, job: F) -> Result<impl FnOnce() + Send + 'static>
     where
         F: FnOnce() + Send + 'static,
     {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::PoolShutDown);
+        }
+        let running = self.in_flight.enter();
+        let queued = Queued::new(Arc::clone(&self.tracker));
+        Ok(move || {
+            let tracker = queued.start();
+            tracker.run(job);
+            drop(running);
+        })
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    fn logger(&self) -> &slog::Logger {
+        &self.tracker.config.logger
+    }
+
+    /// Waits for jobs to finish until `deadline`, returning how many are left
+    fn drain(&self, deadline: Option<Instant>) -> usize {
+        self.in_flight.drain(until(deadline))
     }
+
+    /// The pool's stats, given how many threads it has
+    fn stats(&self, workers: usize) -> PoolStats {
+        let tracker = &self.tracker;
+        let active = tracker.active.load(Ordering::SeqCst);
+        PoolStats {
+            active_workers: active,
+            idle_workers: workers.saturating_sub(active),
+            queued_jobs: tracker.queued.load(Ordering::SeqCst),
+            completed_jobs: tracker.completed.load(Ordering::SeqCst),
+            panicked_jobs: tracker.panicked.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl Tracker {
+    fn run<F: FnOnce()>(&self, job: F) {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        let result = panic::catch_unwind(AssertUnwindSafe(job));
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        match result {
+            Ok(()) => {
+                self.completed.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(payload) => {
+                self.panicked.fetch_add(1, Ordering::SeqCst);
+                error!(self.config.logger, "job panicked"; "panic" => panic_message(&*payload));
+                if let Some(handler) = &self.config.panic_handler {
+                    handler(&*payload);
+                }
+            }
+        }
+    }
+}
+
+// Counts a job as queued for as long as it is held
+struct Queued(Option<Arc<Tracker>>);
+
+impl Queued {
+    fn new(tracker: Arc<Tracker>) -> Queued {
+        tracker.queued.fetch_add(1, Ordering::SeqCst);
+        Queued(Some(tracker))
+    }
+
+    fn start(mut self) -> Arc<Tracker> {
+        let tracker = self.0.take().unwrap();
+        tracker.queued.fetch_sub(1, Ordering::SeqCst);
+        tracker
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        if let Some(tracker) = self.0.take() {
+            tracker.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// The message a job panicked with, if it was a string
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+// The time left before `deadline`, where `None` never comes
+fn until(deadline: Option<Instant>) -> Duration {
+    deadline.map_or(Duration::MAX, |deadline| {
+        deadline.saturating_duration_since(Instant::now())
+    })
 }
 
+mod handle;
 mod naive;
 mod rayon;
 mod shared_queue;
+mod work_stealing;
 
+pub use self::handle::{JobError, JobHandle};
 pub use self::naive::NaiveThreadPool;
 pub use self::rayon::RayonThreadPool;
-pub use self::shared_queue::SharedQueueThreadPool;
+pub use self::shared_queue::{SharedQueueOptions, SharedQueueThreadPool};
+pub use self::work_stealing::WorkStealingThreadPool;
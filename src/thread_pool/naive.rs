@@ -1,18 +1,49 @@
+use super::{Jobs, PoolConfig, PoolStats};
 use crate::Result;
 use crate::ThreadPool;
 use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct NaiveThreadPool;
+/// Runs each job on a thread of its own
+pub struct NaiveThreadPool {
+    jobs: Jobs,
+}
 
 impl ThreadPool for NaiveThreadPool {
-    fn new(_threads: u32) -> Result<NaiveThreadPool> {
-        Ok(NaiveThreadPool {})
+    fn with_config(_threads: u32, config: PoolConfig) -> Result<NaiveThreadPool> {
+        Ok(NaiveThreadPool {
+            jobs: Jobs::new(config),
+        })
     }
 
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        thread::spawn(move || job());
+        let _ = self.try_spawn(job);
+    }
+
+    fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::spawn(self.jobs.wrap(job)?);
+        Ok(())
+    }
+
+    fn resize(&self, _threads: u32) -> Result<()> {
+        // There is no fixed set of threads to resize
+        Ok(())
+    }
+
+    fn shutdown(&self, timeout: Duration) -> usize {
+        self.jobs.close();
+        // Each thread exits with its job, so waiting for the jobs joins them too
+        self.jobs.drain(Instant::now().checked_add(timeout))
+    }
+
+    fn stats(&self) -> PoolStats {
+        // Threads come and go with their jobs, so none is ever idle
+        self.jobs.stats(0)
     }
 }
@@ -1,25 +1,65 @@
-use super::ThreadPool;
+use super::{Jobs, PoolConfig, PoolStats, ThreadPool};
 use crate::Result;
 use rayon;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 
-pub struct RayonThreadPool(rayon::ThreadPool);
+pub struct RayonThreadPool {
+    // Taken on shutdown, letting rayon's threads exit
+    pool: RwLock<Option<rayon::ThreadPool>>,
+    jobs: Jobs,
+}
 
 impl ThreadPool for RayonThreadPool {
-    fn new(threads: u32) -> Result<Self>
+    fn with_config(threads: u32, config: PoolConfig) -> Result<Self>
     where
         Self: Sized,
     {
-        Ok(RayonThreadPool(
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(threads as usize)
-                .build()?,
-        ))
+        Ok(RayonThreadPool {
+            pool: RwLock::new(Some(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads as usize)
+                    .build()?,
+            )),
+            jobs: Jobs::new(config),
+        })
     }
 
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.0.install(job);
+        let _ = self.try_spawn(job);
+    }
+
+    fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // Rayon aborts the process on a panic in a spawned job, so it matters
+        // that `wrap` catches them
+        let job = self.jobs.wrap(job)?;
+        if let Some(pool) = &*self.pool.read().unwrap() {
+            pool.spawn(job);
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self, timeout: Duration) -> usize {
+        self.jobs.close();
+        let abandoned = self.jobs.drain(Instant::now().checked_add(timeout));
+        // Dropping the pool lets its threads exit once any abandoned jobs finish
+        self.pool.write().unwrap().take();
+        abandoned
+    }
+
+    fn stats(&self) -> PoolStats {
+        let threads = self
+            .pool
+            .read()
+            .unwrap()
+            .as_ref()
+            .map_or(0, rayon::ThreadPool::current_num_threads);
+        self.jobs.stats(threads)
     }
 }
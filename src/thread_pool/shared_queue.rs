@@ -1,72 +1,280 @@
-use crate::Result;
+use crate::shutdown::InFlight;
+use crate::{Error, Result};
 
-use super::ThreadPool;
-use std::panic;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use super::{until, Jobs, OverflowPolicy, PoolConfig, PoolStats, ThreadPool};
+use slog::debug;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 type Thunk = Box<dyn FnOnce() + Send + 'static>;
 
-struct Worker(Arc<Mutex<Receiver<Thunk>>>);
+/// How a `SharedQueueThreadPool` sizes itself and its queue
+#[derive(Clone, Copy, Debug)]
+pub struct SharedQueueOptions {
+    /// Threads kept however quiet the pool gets
+    pub min_threads: u32,
+    /// Threads the pool grows to while jobs wait for one
+    pub max_threads: u32,
+    /// How long a thread above `min_threads` waits for a job before exiting
+    pub idle_timeout: Duration,
+    /// Jobs the queue holds before `overflow` applies, or `None` for no limit
+    pub queue_capacity: Option<usize>,
+    pub overflow: OverflowPolicy,
+}
 
-impl Drop for Worker {
-    fn drop(&mut self) {
-        if thread::panicking() {
-            println!("Thread panicking... dropping");
-            spawn_in_pool(Worker(self.0.clone()));
+impl SharedQueueOptions {
+    /// Options for a pool of exactly `threads` threads with an unbounded queue
+    pub fn fixed(threads: u32) -> SharedQueueOptions {
+        SharedQueueOptions {
+            min_threads: threads,
+            max_threads: threads,
+            idle_timeout: Duration::from_secs(60),
+            queue_capacity: None,
+            overflow: OverflowPolicy::Block,
         }
     }
 }
 
-fn spawn_in_pool(job_receiver: Worker) {
-    thread::spawn(move || loop {
-        // Only lock for the duration required to receive a job
-        // Not for also executing a job
-        let message = {
-            let lock = job_receiver
-                .0
-                .lock()
-                .expect("Worker thread unable to lock job_receiver");
-
-            lock.recv()
-        };
+/// The jobs waiting for a worker, optionally bounded, and the count of
+/// workers taking them
+struct Queue {
+    state: Mutex<QueueState>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    idle_timeout: Duration,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
 
-        match message {
-            Ok(job) => {
-                println!("Thread got a job, executing...");
-                job();
+struct QueueState {
+    jobs: VecDeque<Thunk>,
+    // Once set, no more jobs are taken and workers exit when the queue is empty
+    closed: bool,
+    // Live workers, and how many of them are waiting for a job
+    workers: u32,
+    idle: u32,
+    min_workers: u32,
+    max_workers: u32,
+}
+
+impl Queue {
+    fn new(options: &SharedQueueOptions) -> Queue {
+        Queue {
+            state: Mutex::new(QueueState {
+                jobs: VecDeque::new(),
+                closed: false,
+                workers: options.min_threads,
+                idle: 0,
+                min_workers: options.min_threads,
+                max_workers: options.max_threads,
+            }),
+            capacity: options.queue_capacity,
+            policy: options.overflow,
+            idle_timeout: options.idle_timeout,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    /// Queues `job`, returning whether the pool should grow by a worker to
+    /// take it because every worker is busy
+    fn push(&self, job: Thunk) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let mut dropped = None;
+        loop {
+            if state.closed {
+                return Err(Error::PoolShutDown);
             }
-            Err(..) => break,
-        };
-    });
+            match self.capacity {
+                Some(capacity) if state.jobs.len() >= capacity => match self.policy {
+                    OverflowPolicy::Block => state = self.not_full.wait(state).unwrap(),
+                    OverflowPolicy::Reject => return Err(Error::QueueFull),
+                    OverflowPolicy::DropOldest => {
+                        dropped = state.jobs.pop_front();
+                        break;
+                    }
+                },
+                _ => break,
+            }
+        }
+        state.jobs.push_back(job);
+        let grow = state.jobs.len() > state.idle as usize && state.workers < state.max_workers;
+        if grow {
+            state.workers += 1;
+        }
+        drop(state);
+        self.not_empty.notify_one();
+        // Whatever the dropped job owns is released outside the lock
+        drop(dropped);
+        Ok(grow)
+    }
+
+    /// Waits for the next job. Returns `None` once the queue is closed and
+    /// empty, or when the calling worker should exit to shrink the pool.
+    fn pop(&self) -> Option<Thunk> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                drop(state);
+                self.not_full.notify_one();
+                return Some(job);
+            }
+            if state.closed || state.workers > state.max_workers {
+                state.workers -= 1;
+                return None;
+            }
+
+            state.idle += 1;
+            let (next, wait) = self
+                .not_empty
+                .wait_timeout(state, self.idle_timeout)
+                .unwrap();
+            state = next;
+            state.idle -= 1;
+            if wait.timed_out() && state.jobs.is_empty() && state.workers > state.min_workers {
+                state.workers -= 1;
+                return None;
+            }
+        }
+    }
+
+    /// Sets both bounds to `threads`, returning how many workers to add
+    fn resize(&self, threads: u32) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        state.min_workers = threads;
+        state.max_workers = threads;
+        let added = threads.saturating_sub(state.workers);
+        state.workers += added;
+        drop(state);
+        // Idle workers over the new size notice and exit
+        self.not_empty.notify_all();
+        added
+    }
+
+    fn workers(&self) -> u32 {
+        self.state.lock().unwrap().workers
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
 }
 
+/// Runs jobs on threads fed from one queue, growing from `min_threads`
+/// towards `max_threads` while jobs wait and shrinking back once threads sit
+/// idle for `idle_timeout`.
+///
+/// The queue is unbounded unless given a `queue_capacity`, past which its
+/// `OverflowPolicy` decides what happens to jobs that arrive while it is full.
 pub struct SharedQueueThreadPool {
-    job_sender: Sender<Thunk>,
+    queue: Arc<Queue>,
+    jobs: Jobs,
+    workers: InFlight,
 }
 
-impl ThreadPool for SharedQueueThreadPool {
-    fn new(threads: u32) -> Result<SharedQueueThreadPool> {
-        assert!(threads > 0);
+impl SharedQueueThreadPool {
+    /// Creates a pool of `threads` threads whose queue holds at most
+    /// `capacity` waiting jobs
+    pub fn with_queue(
+        threads: u32,
+        capacity: usize,
+        policy: OverflowPolicy,
+        config: PoolConfig,
+    ) -> Result<SharedQueueThreadPool> {
+        let options = SharedQueueOptions {
+            queue_capacity: Some(capacity),
+            overflow: policy,
+            ..SharedQueueOptions::fixed(threads)
+        };
+        SharedQueueThreadPool::with_options(options, config)
+    }
 
-        let (tx, rx) = channel::<Thunk>();
-        let locked_receiver = Arc::new(Mutex::new(rx));
+    /// Creates a pool sized, and with a queue bounded, as `options` says
+    pub fn with_options(
+        options: SharedQueueOptions,
+        config: PoolConfig,
+    ) -> Result<SharedQueueThreadPool> {
+        assert!(options.max_threads > 0);
+        assert!(options.min_threads <= options.max_threads);
+        assert!(options.queue_capacity != Some(0));
 
-        for _ in 0..threads {
-            let job_receiver = Arc::clone(&locked_receiver);
-            spawn_in_pool(Worker(job_receiver));
+        let pool = SharedQueueThreadPool {
+            queue: Arc::new(Queue::new(&options)),
+            jobs: Jobs::new(config),
+            workers: InFlight::default(),
+        };
+        for _ in 0..options.min_threads {
+            pool.add_worker();
         }
+        Ok(pool)
+    }
 
-        Ok(SharedQueueThreadPool { job_sender: tx })
+    // The queue has already counted the worker
+    fn add_worker(&self) {
+        let queue = Arc::clone(&self.queue);
+        let alive = self.workers.enter();
+        let logger = self.jobs.logger().clone();
+        thread::spawn(move || {
+            let _alive = alive;
+            // `Jobs` catches panicking jobs, so a worker only exits once the
+            // queue is closed and empty or the pool shrinks
+            while let Some(job) = queue.pop() {
+                job();
+            }
+            debug!(logger, "pool thread exiting");
+        });
+    }
+}
+
+impl ThreadPool for SharedQueueThreadPool {
+    fn with_config(threads: u32, config: PoolConfig) -> Result<SharedQueueThreadPool> {
+        SharedQueueThreadPool::with_options(SharedQueueOptions::fixed(threads), config)
     }
 
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.job_sender
-            .send(Box::new(job))
-            .expect("ThreadPool::spawn unable to send jobs into queue");
+        let _ = self.try_spawn(job);
+    }
+
+    fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.queue.push(Box::new(self.jobs.wrap(job)?))? {
+            debug!(self.jobs.logger(), "every pool thread busy, adding one");
+            self.add_worker();
+        }
+        Ok(())
+    }
+
+    fn resize(&self, threads: u32) -> Result<()> {
+        if threads == 0 {
+            return Err(Error::Unsupported(
+                "a pool needs at least one thread".to_owned(),
+            ));
+        }
+        for _ in 0..self.queue.resize(threads) {
+            self.add_worker();
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now().checked_add(timeout);
+        self.jobs.close();
+        self.queue.close();
+        let abandoned = self.jobs.drain(deadline);
+        self.workers.drain(until(deadline));
+        abandoned
+    }
+
+    fn stats(&self) -> PoolStats {
+        self.jobs.stats(self.queue.workers() as usize)
     }
 }
@@ -0,0 +1,133 @@
+use super::{until, Jobs, PoolConfig, PoolStats, ThreadPool};
+use crate::shutdown::{InFlight, InFlightGuard};
+use crate::Result;
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use std::iter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Thunk = Box<dyn FnOnce() + Send + 'static>;
+
+/// What the pool's threads share. New jobs land in `injector`, and each
+/// thread moves them over to its own deque in batches, so threads contend
+/// for the injector far less often than for a single shared queue.
+struct Shared {
+    injector: Injector<Thunk>,
+    stealers: Vec<Stealer<Thunk>>,
+    closed: AtomicBool,
+    // Idle threads wait on `wake`, holding `sleep` while they check for work
+    sleep: Mutex<()>,
+    wake: Condvar,
+}
+
+impl Shared {
+    /// Takes a job from `local`, else a batch from the injector, else one
+    /// from another thread's deque
+    fn find_job(&self, local: &Worker<Thunk>) -> Option<Thunk> {
+        local.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    fn has_work(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|stealer| !stealer.is_empty())
+    }
+}
+
+fn run_worker(local: Worker<Thunk>, shared: Arc<Shared>, _alive: InFlightGuard) {
+    loop {
+        // `Jobs` catches panicking jobs, so this thread and the jobs in its
+        // deque outlive them
+        if let Some(job) = shared.find_job(&local) {
+            job();
+            continue;
+        }
+
+        let sleep = shared.sleep.lock().unwrap();
+        if shared.has_work() {
+            continue;
+        }
+        if shared.closed.load(Ordering::SeqCst) {
+            break;
+        }
+        drop(shared.wake.wait(sleep).unwrap());
+    }
+}
+
+/// Runs jobs on a fixed set of threads, each with a deque of its own that the
+/// others steal from when they run out of work
+pub struct WorkStealingThreadPool {
+    shared: Arc<Shared>,
+    jobs: Jobs,
+    workers: InFlight,
+}
+
+impl ThreadPool for WorkStealingThreadPool {
+    fn with_config(threads: u32, config: PoolConfig) -> Result<WorkStealingThreadPool> {
+        assert!(threads > 0);
+
+        let locals: Vec<_> = (0..threads).map(|_| Worker::new_fifo()).collect();
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: locals.iter().map(Worker::stealer).collect(),
+            closed: AtomicBool::new(false),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+        });
+        let workers = InFlight::default();
+
+        for local in locals {
+            let shared = Arc::clone(&shared);
+            let alive = workers.enter();
+            thread::spawn(move || run_worker(local, shared, alive));
+        }
+
+        Ok(WorkStealingThreadPool {
+            shared,
+            jobs: Jobs::new(config),
+            workers,
+        })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let _ = self.try_spawn(job);
+    }
+
+    fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.injector.push(Box::new(self.jobs.wrap(job)?));
+        // Taking the lock means a thread that just found nothing to do is
+        // either still looking, and will see the job, or already waiting
+        drop(self.shared.sleep.lock().unwrap());
+        self.shared.wake.notify_one();
+        Ok(())
+    }
+
+    fn shutdown(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now().checked_add(timeout);
+        self.jobs.close();
+        self.shared.closed.store(true, Ordering::SeqCst);
+        drop(self.shared.sleep.lock().unwrap());
+        self.shared.wake.notify_all();
+        let abandoned = self.jobs.drain(deadline);
+        self.workers.drain(until(deadline));
+        abandoned
+    }
+
+    fn stats(&self) -> PoolStats {
+        self.jobs.stats(self.workers.count())
+    }
+}
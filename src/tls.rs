@@ -0,0 +1,113 @@
+//! TLS settings for `KvsServer` and `KvsClient`, loaded from PEM files.
+
+use crate::error::Error;
+use crate::transport::Address;
+use crate::Result;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Server-side TLS. If `client_ca` is set, clients must present a certificate signed by it.
+#[derive(Clone, Debug)]
+pub struct ServerTlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl ServerTlsConfig {
+    pub(crate) fn load(&self) -> Result<Arc<ServerConfig>> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(load_roots(ca)?),
+                    provider,
+                )
+                .build()
+                .map_err(|e| Error::TlsConfig(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Arc::new(builder.with_single_cert(
+            load_certs(&self.cert)?,
+            load_key(&self.key)?,
+        )?))
+    }
+}
+
+/// Client-side TLS. `cert` and `key` are only needed when the server verifies clients.
+#[derive(Clone, Debug)]
+pub struct ClientTlsConfig {
+    /// The CA used to verify the server's certificate
+    pub ca: PathBuf,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    /// The name to verify the server's certificate against. Defaults to the address's IP.
+    pub server_name: Option<String>,
+}
+
+impl ClientTlsConfig {
+    pub(crate) fn load(&self) -> Result<Arc<ClientConfig>> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(load_roots(&self.ca)?);
+        let config = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(Error::TlsConfig(
+                    "a client certificate and key must be given together".to_owned(),
+                ))
+            }
+        };
+        Ok(Arc::new(config))
+    }
+
+    pub(crate) fn server_name(&self, address: &Address) -> Result<ServerName<'static>> {
+        match (&self.server_name, address) {
+            (Some(name), _) => ServerName::try_from(name.clone())
+                .map_err(|e| Error::TlsConfig(format!("invalid server name: {}", e))),
+            (None, Address::Tcp(addr)) => Ok(ServerName::IpAddress(addr.ip().into())),
+            (None, Address::Unix(_)) => Err(Error::TlsConfig(
+                "a server name is required for TLS over a Unix socket".to_owned(),
+            )),
+        }
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(Error::TlsConfig(format!(
+            "no certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut BufReader::new(File::open(path)?))?
+        .ok_or_else(|| Error::TlsConfig(format!("no private key found in {}", path.display())))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
@@ -0,0 +1,271 @@
+//! Transports the server can listen on and the client can connect over.
+
+use crate::Result;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, ServerConfig, ServerConnection, StreamOwned};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{AddrParseError, IpAddr, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const UNIX_PREFIX: &str = "unix:";
+
+/// Where a server listens: either `IP:PORT` or `unix:/path/to/socket`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Address, AddrParseError> {
+        match s.strip_prefix(UNIX_PREFIX) {
+            Some(path) if !path.is_empty() => Ok(Address::Unix(PathBuf::from(path))),
+            _ => s.parse().map(Address::Tcp),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            Address::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(addr: SocketAddr) -> Address {
+        Address::Tcp(addr)
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Binds to `address`. Unix sockets replace any stale socket file and, if
+    /// `unix_mode` is set, get those permission bits. Anything else already at
+    /// the path is left alone and the bind fails with `AddrInUse`.
+    pub fn bind(address: &Address, unix_mode: Option<u32>) -> io::Result<Listener> {
+        match address {
+            Address::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr)?)),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(io::ErrorKind::AddrInUse.into());
+                }
+                match std::fs::symlink_metadata(path) {
+                    Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("{} exists and is not a socket", path.display()),
+                        ))
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+                let listener = match unix_mode {
+                    Some(mode) => bind_unix_with_mode(path, mode)?,
+                    None => UnixListener::bind(path)?,
+                };
+                Ok(Listener::Unix(listener, path.clone()))
+            }
+            #[cfg(not(unix))]
+            Address::Unix(_) => {
+                let _ = unix_mode;
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unix sockets are not supported on this platform",
+                ))
+            }
+        }
+    }
+
+    /// The address clients should connect to, with any wildcard port resolved
+    pub fn local_address(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => Ok(Address::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(Address::Unix(path.clone())),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => Ok(Stream::Tcp(listener.accept()?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Stream::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+/// Binds a Unix socket at `path` that is never reachable with looser
+/// permissions than `mode`: it is bound inside a directory only this user can
+/// enter, given its mode there, then moved into place.
+#[cfg(unix)]
+fn bind_unix_with_mode(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // Kept short, as socket paths are limited to around a hundred bytes
+    let staging = parent.join(format!(".kvs.{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("s");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    if bound.is_err() {
+        let _ = std::fs::remove_file(&staged);
+    }
+    let _ = std::fs::remove_dir(&staging);
+    bound
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    TlsServer(Box<StreamOwned<ServerConnection, Stream>>),
+    TlsClient(Box<StreamOwned<ClientConnection, Stream>>),
+}
+
+impl Stream {
+    pub fn connect(address: &Address) -> io::Result<Stream> {
+        match address {
+            Address::Tcp(addr) => Ok(Stream::Tcp(TcpStream::connect(addr)?)),
+            #[cfg(unix)]
+            Address::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    /// The remote IP, or `None` for Unix sockets
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().ok().map(|addr| addr.ip()),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+            Stream::TlsServer(stream) => stream.sock.peer_ip(),
+            Stream::TlsClient(stream) => stream.sock.peer_ip(),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            Stream::TlsServer(stream) => stream.sock.set_read_timeout(timeout),
+            Stream::TlsClient(stream) => stream.sock.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+            Stream::TlsServer(stream) => stream.sock.set_write_timeout(timeout),
+            Stream::TlsClient(stream) => stream.sock.set_write_timeout(timeout),
+        }
+    }
+
+    /// Like `connect`, giving up on TCP connections after `timeout`. Unix
+    /// sockets connect or fail immediately, so ignore it.
+    pub fn connect_timeout(address: &Address, timeout: Option<Duration>) -> io::Result<Stream> {
+        match (address, timeout) {
+            (Address::Tcp(addr), Some(timeout)) => {
+                Ok(Stream::Tcp(TcpStream::connect_timeout(addr, timeout)?))
+            }
+            _ => Stream::connect(address),
+        }
+    }
+
+    /// Wraps a connected stream as the client end of a TLS session and
+    /// completes the handshake, so certificate problems surface here
+    pub fn into_tls_client(
+        self,
+        config: Arc<ClientConfig>,
+        server_name: ServerName<'static>,
+    ) -> Result<Stream> {
+        let connection = ClientConnection::new(config, server_name)?;
+        let mut stream = StreamOwned::new(connection, self);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        Ok(Stream::TlsClient(Box::new(stream)))
+    }
+
+    /// Wraps an accepted stream as the server end of a TLS session. The
+    /// handshake runs lazily on first read, on the serving thread.
+    pub fn accept_tls(self, config: Arc<ServerConfig>) -> Result<Stream> {
+        let connection = ServerConnection::new(config)?;
+        Ok(Stream::TlsServer(Box::new(StreamOwned::new(
+            connection, self,
+        ))))
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::TlsServer(stream) => stream.read(buf),
+            Stream::TlsClient(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::TlsServer(stream) => stream.write(buf),
+            Stream::TlsClient(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+            Stream::TlsServer(stream) => stream.flush(),
+            Stream::TlsClient(stream) => stream.flush(),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use kvs::async_server::AsyncKvsServer;
+use kvs::auth::{Acl, Credentials};
+use kvs::command::{Admin, Request, Response};
+use kvs::rate_limit::RateLimit;
+use kvs::server::{KvsServer, ServerConfig};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{Error, KvStore, KvsClient, Result, ShutdownHandle};
+use std::fs;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const ACL: &str = r#"{
+    "principals": [
+        { "name": "admin", "password": "hunter2", "grants": [{ "prefix": "", "access": "admin" }] },
+        {
+            "name": "reports",
+            "token": "t0ken",
+            "grants": [
+                { "prefix": "reports/", "access": "write" },
+                { "prefix": "shared/", "access": "read" }
+            ]
+        }
+    ]
+}"#;
+
+fn start_server(addr: SocketAddr, temp_dir: &TempDir) -> ShutdownHandle {
+    let acl_file = temp_dir.path().join("acl.json");
+    fs::write(&acl_file, ACL).unwrap();
+    let config = ServerConfig {
+        acl: Some(Acl::load(&acl_file).unwrap()),
+        ..ServerConfig::default()
+    };
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path()).unwrap(),
+        SharedQueueThreadPool::new(2).unwrap(),
+        slog::Logger::root(slog::Discard, slog::o!()),
+        config,
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+    handle
+}
+
+fn set(key: &str) -> Request {
+    Request::Set(key.to_owned(), "value".to_owned())
+}
+
+#[test]
+fn requests_require_authentication() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4140".parse().unwrap();
+    let handle = start_server(addr, &temp_dir);
+
+    let result = KvsClient::send(Request::Get("key1".to_owned()), addr);
+    assert!(matches!(result, Err(Error::Unauthorized(_))));
+
+    let wrong_password = KvsClient::builder(addr)
+        .credentials(Credentials::Password {
+            user: "admin".to_owned(),
+            password: "hunter3".to_owned(),
+        })
+        .build()?;
+    let result = wrong_password.request(Request::Get("key1".to_owned()));
+    assert!(matches!(result, Err(Error::Unauthorized(_))));
+
+    let wrong_token = KvsClient::builder(addr)
+        .credentials(Credentials::Token("nope".to_owned()))
+        .build()?;
+    let result = wrong_token.request(Request::Get("key1".to_owned()));
+    assert!(matches!(result, Err(Error::Unauthorized(_))));
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn grants_are_enforced_per_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4141".parse().unwrap();
+    let handle = start_server(addr, &temp_dir);
+
+    let admin = KvsClient::builder(addr)
+        .credentials(Credentials::Password {
+            user: "admin".to_owned(),
+            password: "hunter2".to_owned(),
+        })
+        .build()?;
+    admin.request(set("shared/motd"))?;
+    admin.request(set("private/key"))?;
+
+    let reports = KvsClient::builder(addr)
+        .credentials(Credentials::Token("t0ken".to_owned()))
+        .build()?;
+    reports.request(set("reports/daily"))?;
+    assert_eq!(
+        reports.request(Request::Get("reports/daily".to_owned()))?,
+        Some("value".to_owned())
+    );
+    assert_eq!(
+        reports.request(Request::Get("shared/motd".to_owned()))?,
+        Some("value".to_owned())
+    );
+    assert!(matches!(
+        reports.request(set("shared/motd")),
+        Err(Error::Unauthorized(_))
+    ));
+    assert!(matches!(
+        reports.request(Request::Rm("shared/motd".to_owned())),
+        Err(Error::Unauthorized(_))
+    ));
+    assert!(matches!(
+        reports.request(Request::Get("private/key".to_owned())),
+        Err(Error::Unauthorized(_))
+    ));
+    // One key out of bounds refuses the whole request
+    assert!(matches!(
+        reports.multi_get(vec!["shared/motd".to_owned(), "private/key".to_owned()]),
+        Err(Error::Unauthorized(_))
+    ));
+    assert!(matches!(
+        reports.multi_set(vec![
+            ("reports/weekly".to_owned(), "value".to_owned()),
+            ("shared/motd".to_owned(), "value".to_owned()),
+        ]),
+        Err(Error::Unauthorized(_))
+    ));
+    assert_eq!(
+        reports.request(Request::Get("reports/weekly".to_owned()))?,
+        None
+    );
+
+    admin.request(Request::Rm("reports/daily".to_owned()))?;
+    assert_eq!(
+        admin.request(Request::Get("reports/daily".to_owned()))?,
+        None
+    );
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn admin_requests_need_admin_access() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4142".parse().unwrap();
+    let handle = start_server(addr, &temp_dir);
+
+    let reports = KvsClient::builder(addr)
+        .credentials(Credentials::Token("t0ken".to_owned()))
+        .build()?;
+    assert!(matches!(
+        reports.call(Request::Admin(Admin::Stats)),
+        Err(Error::Unauthorized(_))
+    ));
+
+    let admin = KvsClient::builder(addr)
+        .credentials(Credentials::Password {
+            user: "admin".to_owned(),
+            password: "hunter2".to_owned(),
+        })
+        .build()?;
+    match admin.call(Request::Admin(Admin::Connections))? {
+        Response::Connections(connections) => {
+            let mut principals: Vec<_> = connections
+                .into_iter()
+                .filter_map(|connection| connection.principal)
+                .collect();
+            principals.sort();
+            assert_eq!(principals, vec!["admin".to_owned(), "reports".to_owned()]);
+        }
+        _ => panic!("expected connections"),
+    }
+    // Secrets stay out of the reported config
+    match admin.call(Request::Admin(Admin::Config))? {
+        Response::Entries(settings) => {
+            assert!(settings.contains(&("acl".to_owned(), "2 principals".to_owned())));
+            assert!(!format!("{:?}", settings).contains("hunter2"));
+        }
+        _ => panic!("expected entries"),
+    }
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn reloaded_grants_apply_to_open_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4143".parse().unwrap();
+    let acl_file = temp_dir.path().join("acl.json");
+    fs::write(&acl_file, ACL).unwrap();
+    let config = ServerConfig {
+        acl: Some(Acl::load(&acl_file)?),
+        ..ServerConfig::default()
+    };
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        slog::Logger::root(slog::Discard, slog::o!()),
+        config.clone(),
+    );
+    let handle = server.shutdown_handle();
+    let reload = server.reload_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    let admin = KvsClient::builder(addr)
+        .credentials(Credentials::Password {
+            user: "admin".to_owned(),
+            password: "hunter2".to_owned(),
+        })
+        .build()?;
+    let reports = KvsClient::builder(addr)
+        .credentials(Credentials::Token("t0ken".to_owned()))
+        .build()?;
+    reports.request(set("reports/daily"))?;
+
+    // reports is cut back to reading, and stays authenticated to do so
+    fs::write(&acl_file, ACL.replace("\"write\"", "\"read\""))?;
+    reload.reload(&ServerConfig {
+        acl: Some(Acl::load(&acl_file)?),
+        ..config.clone()
+    })?;
+    assert!(matches!(
+        reports.request(set("reports/daily")),
+        Err(Error::Unauthorized(_))
+    ));
+    assert_eq!(
+        reports.request(Request::Get("reports/daily".to_owned()))?,
+        Some("value".to_owned())
+    );
+    admin.request(set("shared/motd"))?;
+
+    // Changing admin's password signs out its open connection, while
+    // reports, whose token is unchanged, carries on
+    fs::write(
+        &acl_file,
+        ACL.replace("\"write\"", "\"read\"")
+            .replace("hunter2", "hunter3"),
+    )?;
+    reload.reload(&ServerConfig {
+        acl: Some(Acl::load(&acl_file)?),
+        ..config
+    })?;
+    assert!(matches!(
+        admin.request(Request::Get("shared/motd".to_owned())),
+        Err(Error::Unauthorized(_))
+    ));
+    assert_eq!(
+        reports.request(Request::Get("reports/daily".to_owned()))?,
+        Some("value".to_owned())
+    );
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn async_server_refuses_reloading_an_acl() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let acl_file = temp_dir.path().join("acl.json");
+    fs::write(&acl_file, ACL)?;
+    let server = AsyncKvsServer::new(
+        KvStore::open(temp_dir.path())?,
+        slog::Logger::root(slog::Discard, slog::o!()),
+    );
+
+    let reload = server.reload_handle();
+    assert!(matches!(
+        reload.reload(&ServerConfig {
+            acl: Some(Acl::load(&acl_file)?),
+            ..ServerConfig::default()
+        }),
+        Err(Error::Unsupported(_))
+    ));
+    reload.reload(&ServerConfig::default())?;
+    Ok(())
+}
+
+#[test]
+fn principals_and_addresses_are_rate_limited_apart() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4144".parse().unwrap();
+    // A principal named like the address it connects from
+    let acl = r#"{
+        "principals": [
+            { "name": "127.0.0.1", "token": "t0ken", "grants": [{ "prefix": "", "access": "read" }] }
+        ]
+    }"#;
+    let acl_file = temp_dir.path().join("acl.json");
+    fs::write(&acl_file, acl).unwrap();
+    let config = ServerConfig {
+        acl: Some(Acl::load(&acl_file)?),
+        rate_limit: Some(RateLimit {
+            per_second: 0.01,
+            burst: 1,
+        }),
+        ..ServerConfig::default()
+    };
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        slog::Logger::root(slog::Discard, slog::o!()),
+        config,
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    // Authenticating spends the address's token, leaving the principal's
+    let client = KvsClient::builder(addr)
+        .credentials(Credentials::Token("t0ken".to_owned()))
+        .build()?;
+    assert_eq!(client.request(Request::Get("key1".to_owned()))?, None);
+    let result = client.request(Request::Get("key1".to_owned()));
+    assert!(matches!(result, Err(Error::Overloaded(_))));
+
+    handle.shutdown();
+    Ok(())
+}
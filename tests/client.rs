@@ -0,0 +1,202 @@
+use kvs::command::Request;
+use kvs::server::{KvsServer, Limits, ServerConfig};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{Error, KvStore, KvsClient, Result, ShutdownHandle};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn start_server(addr: SocketAddr, temp_dir: &TempDir, config: ServerConfig) -> ShutdownHandle {
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path()).unwrap(),
+        SharedQueueThreadPool::new(4).unwrap(),
+        slog::Logger::root(slog::Discard, slog::o!()),
+        config,
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+    handle
+}
+
+#[test]
+fn reuses_pooled_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4150".parse().unwrap();
+    let config = ServerConfig {
+        limits: Limits {
+            max_connections: Some(1),
+            ..Limits::default()
+        },
+        ..ServerConfig::default()
+    };
+    let handle = start_server(addr, &temp_dir, config);
+
+    let client = KvsClient::new(addr);
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+    // The pooled connection holds the server's only slot, so a second
+    // client is turned away while the first keeps going
+    assert!(matches!(
+        KvsClient::send(Request::Get("key1".to_owned()), addr),
+        Err(Error::Rejected(_))
+    ));
+    assert_eq!(
+        client.request(Request::Get("key1".to_owned()))?,
+        Some("value1".to_owned())
+    );
+
+    drop(client);
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn reconnects_when_a_pooled_connection_was_closed() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4155".parse().unwrap();
+    // The old server hangs up on the pooled connection once it sits idle
+    let config = ServerConfig {
+        limits: Limits {
+            idle_timeout: Some(Duration::from_millis(200)),
+            ..Limits::default()
+        },
+        ..ServerConfig::default()
+    };
+    let handle = start_server(addr, &temp_dir, config.clone());
+
+    // Without retries, only the stale connection's own second chance can succeed
+    let client = KvsClient::builder(addr)
+        .retries(0, Duration::from_millis(50))
+        .build()?;
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+
+    handle.shutdown();
+    thread::sleep(Duration::from_millis(500));
+    let handle = start_server(addr, &temp_dir, config);
+
+    assert_eq!(
+        client.request(Request::Get("key1".to_owned()))?,
+        Some("value1".to_owned())
+    );
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn does_not_replay_writes_on_a_closed_pooled_connection() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4156".parse().unwrap();
+    // The server hangs up on the pooled connection once it sits idle
+    let config = ServerConfig {
+        limits: Limits {
+            idle_timeout: Some(Duration::from_millis(200)),
+            ..Limits::default()
+        },
+        ..ServerConfig::default()
+    };
+    let handle = start_server(addr, &temp_dir, config);
+
+    let client = KvsClient::builder(addr)
+        .retries(0, Duration::from_millis(50))
+        .build()?;
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+    thread::sleep(Duration::from_millis(500));
+
+    // The remove is written to the closed connection before the client can
+    // tell, so it may have been applied and is not sent again
+    assert!(matches!(
+        client.request(Request::Rm("key1".to_owned())),
+        Err(Error::Io(_))
+    ));
+    assert_eq!(
+        KvsClient::send(Request::Get("key1".to_owned()), addr)?,
+        Some("value1".to_owned())
+    );
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn shared_between_threads() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4151".parse().unwrap();
+    let handle = start_server(addr, &temp_dir, ServerConfig::default());
+
+    let client = Arc::new(
+        KvsClient::builder(addr)
+            .pool(2, Duration::from_secs(30))
+            .build()?,
+    );
+    let workers: Vec<_> = (0..8)
+        .map(|i| {
+            let client = client.clone();
+            thread::spawn(move || -> Result<()> {
+                for j in 0..20 {
+                    let key = format!("key{}-{}", i, j);
+                    client.request(Request::Set(key.clone(), j.to_string()))?;
+                    assert_eq!(client.request(Request::Get(key))?, Some(j.to_string()));
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap()?;
+    }
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn fails_over_to_the_next_address() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let down: SocketAddr = "127.0.0.1:4152".parse().unwrap();
+    let up: SocketAddr = "127.0.0.1:4153".parse().unwrap();
+    let handle = start_server(up, &temp_dir, ServerConfig::default());
+
+    let client = KvsClient::builder(down)
+        .failover(up)
+        .retries(2, Duration::from_millis(10))
+        .build()?;
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+    assert_eq!(
+        client.request(Request::Get("key1".to_owned()))?,
+        Some("value1".to_owned())
+    );
+
+    // Without retries the dead address is all the client gets to try
+    let client = KvsClient::builder(down)
+        .failover(up)
+        .retries(0, Duration::from_millis(10))
+        .build()?;
+    assert!(matches!(
+        client.request(Request::Get("key1".to_owned())),
+        Err(Error::Io(_))
+    ));
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn gives_up_on_unresponsive_servers() -> Result<()> {
+    let addr: SocketAddr = "127.0.0.1:4154".parse().unwrap();
+    // Accepts connections but never answers them
+    let listener = TcpListener::bind(addr)?;
+    let _accepted = thread::spawn(move || listener.incoming().collect::<Vec<_>>());
+
+    let client = KvsClient::builder(addr)
+        .request_timeout(Some(Duration::from_millis(200)))
+        .retries(1, Duration::from_millis(10))
+        .build()?;
+    let started = Instant::now();
+    assert!(client.request(Request::Get("key1".to_owned())).is_err());
+    // One attempt plus one retry, each timing out
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(400));
+    assert!(elapsed < Duration::from_secs(5));
+    Ok(())
+}
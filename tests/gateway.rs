@@ -0,0 +1,165 @@
+use kvs::gateway::HttpGateway;
+use kvs::server::Limits;
+use kvs::{KvStore, KvsEngine, NaiveThreadPool, ThreadPool};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn start_gateway(addr: &'static str) -> (KvStore, TempDir) {
+    start_gateway_with_limits(addr, Limits::default())
+}
+
+fn start_gateway_with_limits(addr: &'static str, limits: Limits) -> (KvStore, TempDir) {
+    let temp_dir = TempDir::new().unwrap();
+    let store = KvStore::open(temp_dir.path()).unwrap();
+    let engine = store.clone();
+    thread::spawn(move || {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let mut gateway =
+            HttpGateway::with_limits(engine, NaiveThreadPool::new(0).unwrap(), logger, limits);
+        gateway.start(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(200));
+    (store, temp_dir)
+}
+
+// Sends a request and returns the status code and JSON body
+fn request(addr: &str, method: &str, path: &str, body: Option<&str>) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let body = body.unwrap_or("");
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn gateway_key_operations() {
+    let addr = "127.0.0.1:4110";
+    let (store, _temp_dir) = start_gateway(addr);
+
+    assert_eq!(
+        request(addr, "PUT", "/keys/key1", Some("\"value1\"")),
+        (200, json!({ "OK": "" }))
+    );
+    assert_eq!(
+        store.get("key1".to_owned()).unwrap(),
+        Some("value1".to_owned())
+    );
+    assert_eq!(
+        request(addr, "GET", "/keys/key1", None),
+        (200, json!({ "OK": "value1" }))
+    );
+
+    // Keys are percent-decoded path segments
+    request(addr, "PUT", "/keys/dir%2Fkey", Some("\"nested\""));
+    assert_eq!(
+        store.get("dir/key".to_owned()).unwrap(),
+        Some("nested".to_owned())
+    );
+
+    assert_eq!(
+        request(addr, "DELETE", "/keys/key1", None),
+        (200, json!({ "OK": "" }))
+    );
+    assert_eq!(
+        request(addr, "GET", "/keys/key1", None),
+        (404, json!("NotFound"))
+    );
+    assert_eq!(
+        request(addr, "DELETE", "/keys/key1", None),
+        (404, json!("NotFound"))
+    );
+
+    let (status, _) = request(addr, "PUT", "/keys/key2", Some("not json"));
+    assert_eq!(status, 400);
+    let (status, _) = request(addr, "POST", "/keys/key2", None);
+    assert_eq!(status, 405);
+    let (status, _) = request(addr, "GET", "/nowhere", None);
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn gateway_scan_health_and_stats() {
+    let addr = "127.0.0.1:4111";
+    let (store, _temp_dir) = start_gateway(addr);
+    store.set("app/a".to_owned(), "1".to_owned()).unwrap();
+    store.set("app/b".to_owned(), "2".to_owned()).unwrap();
+    store.set("other".to_owned(), "3".to_owned()).unwrap();
+
+    assert_eq!(
+        request(addr, "GET", "/keys?prefix=app%2F", None),
+        (200, json!({ "Entries": [["app/a", "1"], ["app/b", "2"]] }))
+    );
+    assert_eq!(
+        request(addr, "GET", "/health", None),
+        (200, json!({ "OK": "healthy" }))
+    );
+
+    let (status, stats) = request(addr, "GET", "/stats", None);
+    assert_eq!(status, 200);
+    assert_eq!(stats["engine"]["keys"], 3);
+    assert_eq!(stats["requests"], 2);
+}
+
+#[test]
+fn gateway_enforces_size_limits() {
+    let addr = "127.0.0.1:4112";
+    let limits = Limits {
+        max_key_size: 4,
+        max_value_size: 8,
+        ..Limits::default()
+    };
+    let (store, _temp_dir) = start_gateway_with_limits(addr, limits);
+
+    let (status, _) = request(addr, "PUT", "/keys/big", Some("\"123456789\""));
+    assert_eq!(status, 413);
+    assert_eq!(store.get("big".to_owned()).unwrap(), None);
+    let (status, _) = request(addr, "GET", "/keys/toolong", None);
+    assert_eq!(status, 413);
+
+    assert_eq!(
+        request(addr, "PUT", "/keys/ok", Some("\"12345678\"")),
+        (200, json!({ "OK": "" }))
+    );
+}
+
+#[test]
+fn gateway_enforces_max_connections() {
+    let addr = "127.0.0.1:4113";
+    let limits = Limits {
+        max_connections: Some(1),
+        idle_timeout: Some(Duration::from_millis(300)),
+        ..Limits::default()
+    };
+    let (_store, _temp_dir) = start_gateway_with_limits(addr, limits);
+
+    // An idle connection holds the only slot until its idle timeout
+    let mut idle = TcpStream::connect(addr).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let (status, _) = request(addr, "GET", "/health", None);
+    assert_eq!(status, 503);
+
+    let mut rest = Vec::new();
+    idle.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        request(addr, "GET", "/health", None),
+        (200, json!({ "OK": "healthy" }))
+    );
+}
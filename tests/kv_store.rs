@@ -1,4 +1,4 @@
-use kvs::{KvStore, KvsEngine, Result};
+use kvs::{KvStore, KvsEngine, Result, SledKvsEngine};
 use std::sync::{Arc, Barrier};
 use std::thread;
 use tempfile::TempDir;
@@ -81,6 +81,32 @@ fn remove_key() -> Result<()> {
     Ok(())
 }
 
+// Should return only the keys under a prefix, in key order
+#[test]
+fn scan_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("app/b".to_owned(), "2".to_owned())?;
+    store.set("app/a".to_owned(), "1".to_owned())?;
+    store.set("other".to_owned(), "3".to_owned())?;
+    store.set("app/c".to_owned(), "4".to_owned())?;
+    store.remove("app/c".to_owned())?;
+
+    let expected = vec![
+        ("app/a".to_owned(), "1".to_owned()),
+        ("app/b".to_owned(), "2".to_owned()),
+    ];
+    assert_eq!(store.scan("app/".to_owned())?, expected);
+    assert_eq!(store.scan("".to_owned())?.len(), 3);
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.scan("app/".to_owned())?, expected);
+
+    Ok(())
+}
+
 // Insert data until total size of the directory decreases.
 // Test data correctness after compaction.
 #[test]
@@ -127,6 +153,56 @@ fn compaction() -> Result<()> {
     panic!("No compaction detected");
 }
 
+#[test]
+fn stats_track_dead_bytes_and_compactions() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    let stats = store.stats()?;
+    assert_eq!(stats.keys, 2);
+    assert_eq!(stats.generations, 1);
+    assert_eq!(stats.dead_bytes, 0);
+
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    let dead_bytes = store.stats()?.dead_bytes;
+    assert!(dead_bytes > 0);
+
+    // Replaying the log on open counts the same dead bytes
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stats()?.dead_bytes, dead_bytes);
+
+    store.compact()?;
+    let stats = store.stats()?;
+    assert_eq!(stats.keys, 1);
+    assert_eq!(stats.dead_bytes, 0);
+    assert_eq!(stats.compactions, 1);
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn sled_stats_count_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let db = sled::open(temp_dir.path())?;
+    db.insert("key0", "value0")?;
+    // Keys already in the tree are counted on open
+    let engine = SledKvsEngine::new(db);
+    assert_eq!(engine.stats()?.keys, 1);
+
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.clone().set("key2".to_owned(), "value2".to_owned())?;
+    engine.set("key1".to_owned(), "value3".to_owned())?;
+    assert_eq!(engine.stats()?.keys, 3);
+    engine.remove("key2".to_owned())?;
+    assert!(engine.remove("key2".to_owned()).is_err());
+    assert_eq!(engine.stats()?.keys, 2);
+    Ok(())
+}
+
 #[test]
 fn concurrent_set() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
@@ -0,0 +1,63 @@
+use kvs::engines::{Manifest, FORMAT_VERSION, MANIFEST_FILE};
+use kvs::{Error, Result};
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn creates_a_manifest_for_new_directories() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let dir = temp_dir.path().join("store");
+
+    let manifest = Manifest::open(&dir, None)?;
+    assert_eq!(manifest.engine, "kvs");
+    assert_eq!(manifest.format_version, FORMAT_VERSION);
+    assert!(dir.join(MANIFEST_FILE).exists());
+
+    // Reopening finds the same store, whether or not the engine is named
+    assert_eq!(Manifest::open(&dir, None)?, manifest);
+    assert_eq!(Manifest::open(&dir, Some("kvs"))?, manifest);
+    let other = Manifest::open(&temp_dir.path().join("other"), Some("sled"))?;
+    assert_eq!(other.engine, "sled");
+    assert_ne!(other.store_id, manifest.store_id);
+    Ok(())
+}
+
+#[test]
+fn refuses_other_engines_and_newer_formats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Manifest::open(temp_dir.path(), Some("sled"))?;
+    assert!(matches!(
+        Manifest::open(temp_dir.path(), Some("kvs")),
+        Err(Error::DataDir(_))
+    ));
+
+    let path = temp_dir.path().join(MANIFEST_FILE);
+    let newer = fs::read_to_string(&path)?.replace(
+        &format!("\"format_version\": {}", FORMAT_VERSION),
+        &format!("\"format_version\": {}", FORMAT_VERSION + 1),
+    );
+    fs::write(&path, newer)?;
+    assert!(matches!(
+        Manifest::open(temp_dir.path(), None),
+        Err(Error::DataDir(_))
+    ));
+
+    let fresh = TempDir::new().expect("unable to create temporary working directory");
+    assert!(matches!(
+        Manifest::open(fresh.path(), Some("rocksdb")),
+        Err(Error::InvalidEngine)
+    ));
+    Ok(())
+}
+
+#[test]
+fn migrates_legacy_engine_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(temp_dir.path().join("engine"), "sled")?;
+
+    let manifest = Manifest::open(temp_dir.path(), None)?;
+    assert_eq!(manifest.engine, "sled");
+    assert!(!temp_dir.path().join("engine").exists());
+    assert_eq!(Manifest::load(temp_dir.path())?, Some(manifest));
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use kvs::memcached::MemcachedServer;
+use kvs::server::Limits;
+use kvs::{KvStore, KvsEngine, NaiveThreadPool, ThreadPool};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+struct Conn {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Conn {
+    fn connect(addr: &str) -> Conn {
+        let stream = TcpStream::connect(addr).unwrap();
+        Conn {
+            writer: stream.try_clone().unwrap(),
+            reader: BufReader::new(stream),
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        self.writer.write_all(data.as_bytes()).unwrap();
+    }
+
+    fn line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.line()
+    }
+}
+
+fn start_server(addr: &'static str) -> (KvStore, TempDir) {
+    start_server_with_limits(addr, Limits::default())
+}
+
+fn start_server_with_limits(addr: &'static str, limits: Limits) -> (KvStore, TempDir) {
+    let temp_dir = TempDir::new().unwrap();
+    let store = KvStore::open(temp_dir.path()).unwrap();
+    let engine = store.clone();
+    thread::spawn(move || {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let mut server =
+            MemcachedServer::with_limits(engine, NaiveThreadPool::new(0).unwrap(), logger, limits);
+        server.start(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(200));
+    (store, temp_dir)
+}
+
+#[test]
+fn memcached_storage_commands() {
+    let (store, _temp_dir) = start_server("127.0.0.1:4100");
+    let mut conn = Conn::connect("127.0.0.1:4100");
+
+    assert_eq!(conn.request("set key1 5 0 6\r\nvalue1\r\n"), "STORED\r\n");
+    assert_eq!(
+        store.get("key1".to_owned()).unwrap(),
+        Some("value1".to_owned())
+    );
+
+    conn.send("get key1 missing\r\n");
+    assert_eq!(conn.line(), "VALUE key1 5 6\r\n");
+    assert_eq!(conn.line(), "value1\r\n");
+    assert_eq!(conn.line(), "END\r\n");
+
+    assert_eq!(conn.request("add key1 0 0 1\r\nx\r\n"), "NOT_STORED\r\n");
+    assert_eq!(conn.request("add key2 0 0 1\r\nx\r\n"), "STORED\r\n");
+    assert_eq!(
+        conn.request("replace key3 0 0 1\r\nx\r\n"),
+        "NOT_STORED\r\n"
+    );
+    assert_eq!(conn.request("replace key2 0 0 1\r\ny\r\n"), "STORED\r\n");
+    assert_eq!(store.get("key2".to_owned()).unwrap(), Some("y".to_owned()));
+
+    assert_eq!(conn.request("delete key2\r\n"), "DELETED\r\n");
+    assert_eq!(conn.request("delete key2\r\n"), "NOT_FOUND\r\n");
+    assert_eq!(store.get("key2".to_owned()).unwrap(), None);
+
+    // noreply suppresses the response entirely
+    conn.send("set key4 0 0 1 noreply\r\nz\r\n");
+    assert_eq!(conn.request("get key4\r\n"), "VALUE key4 0 1\r\n");
+    assert_eq!(conn.line(), "z\r\n");
+    assert_eq!(conn.line(), "END\r\n");
+
+    assert_eq!(conn.request("bogus\r\n"), "ERROR\r\n");
+    assert_eq!(
+        conn.request("set key5 0 0 3\r\ntoolong\r\n"),
+        "CLIENT_ERROR bad data chunk\r\n"
+    );
+}
+
+#[test]
+fn memcached_cas() {
+    let (_store, _temp_dir) = start_server("127.0.0.1:4101");
+    let mut conn = Conn::connect("127.0.0.1:4101");
+
+    assert_eq!(conn.request("cas key1 0 0 1 1\r\na\r\n"), "NOT_FOUND\r\n");
+    assert_eq!(conn.request("set key1 0 0 1\r\na\r\n"), "STORED\r\n");
+
+    let header = conn.request("gets key1\r\n");
+    assert_eq!(conn.line(), "a\r\n");
+    assert_eq!(conn.line(), "END\r\n");
+    let unique: u64 = header.split_whitespace().nth(4).unwrap().parse().unwrap();
+
+    assert_eq!(
+        conn.request(&format!("cas key1 0 0 1 {}\r\nb\r\n", unique)),
+        "STORED\r\n"
+    );
+    // The unique changed with the previous write
+    assert_eq!(
+        conn.request(&format!("cas key1 0 0 1 {}\r\nc\r\n", unique)),
+        "EXISTS\r\n"
+    );
+}
+
+#[test]
+fn memcached_incr_decr() {
+    let (store, _temp_dir) = start_server("127.0.0.1:4102");
+    let mut conn = Conn::connect("127.0.0.1:4102");
+
+    assert_eq!(conn.request("incr counter 1\r\n"), "NOT_FOUND\r\n");
+    assert_eq!(conn.request("set counter 0 0 2\r\n10\r\n"), "STORED\r\n");
+    assert_eq!(conn.request("incr counter 5\r\n"), "15\r\n");
+    assert_eq!(conn.request("decr counter 20\r\n"), "0\r\n");
+    assert_eq!(
+        store.get("counter".to_owned()).unwrap(),
+        Some("0".to_owned())
+    );
+
+    assert_eq!(conn.request("set text 0 0 3\r\nabc\r\n"), "STORED\r\n");
+    assert_eq!(
+        conn.request("incr text 1\r\n"),
+        "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n"
+    );
+}
+
+#[test]
+fn memcached_expiry() {
+    let (store, _temp_dir) = start_server("127.0.0.1:4103");
+    let mut conn = Conn::connect("127.0.0.1:4103");
+
+    assert_eq!(conn.request("set short 0 1 1\r\na\r\n"), "STORED\r\n");
+    assert_eq!(conn.request("set gone 0 -1 1\r\na\r\n"), "STORED\r\n");
+    assert_eq!(conn.request("get gone\r\n"), "END\r\n");
+
+    thread::sleep(Duration::from_millis(2100));
+    assert_eq!(conn.request("get short\r\n"), "END\r\n");
+    assert_eq!(store.get("short".to_owned()).unwrap(), None);
+
+    conn.send("quit\r\n");
+    let mut rest = Vec::new();
+    conn.reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn memcached_refuses_items_over_the_size_limit() {
+    let limits = Limits {
+        max_value_size: 8,
+        ..Limits::default()
+    };
+    let (store, _temp_dir) = start_server_with_limits("127.0.0.1:4104", limits);
+    let mut conn = Conn::connect("127.0.0.1:4104");
+
+    assert_eq!(
+        conn.request("set big 0 0 9\r\n123456789\r\n"),
+        "SERVER_ERROR object too large for cache\r\n"
+    );
+    assert_eq!(store.get("big".to_owned()).unwrap(), None);
+
+    // The refused data block is skipped, so the connection carries on
+    assert_eq!(
+        conn.request("set small 0 0 8\r\n12345678\r\n"),
+        "STORED\r\n"
+    );
+
+    // A length that would overflow is refused rather than read
+    assert_eq!(
+        conn.request(&format!("set huge 0 0 {}\r\n", usize::MAX)),
+        "SERVER_ERROR object too large for cache\r\n"
+    );
+}
+
+#[test]
+fn memcached_enforces_connection_limits() {
+    let limits = Limits {
+        max_connections: Some(1),
+        idle_timeout: Some(Duration::from_millis(300)),
+        ..Limits::default()
+    };
+    let (_store, _temp_dir) = start_server_with_limits("127.0.0.1:4105", limits);
+
+    let mut first = Conn::connect("127.0.0.1:4105");
+    assert!(first.request("version\r\n").starts_with("VERSION "));
+
+    let mut second = Conn::connect("127.0.0.1:4105");
+    assert_eq!(second.line(), "SERVER_ERROR too many open connections\r\n");
+
+    // The idle connection is closed, making room for another
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(first.line(), "");
+    let mut third = Conn::connect("127.0.0.1:4105");
+    assert!(third.request("version\r\n").starts_with("VERSION "));
+}
+
+#[test]
+fn memcached_reports_engine_errors_and_keeps_the_connection() {
+    let (_store, temp_dir) = start_server("127.0.0.1:4106");
+    let mut conn = Conn::connect("127.0.0.1:4106");
+    assert_eq!(conn.request("set key1 0 0 6\r\nvalue1\r\n"), "STORED\r\n");
+
+    // With its log gone, the store can no longer read the value back
+    for entry in fs::read_dir(temp_dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "kvstore") {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    assert!(conn.request("get key1\r\n").starts_with("SERVER_ERROR "));
+    assert!(conn.request("version\r\n").starts_with("VERSION "));
+}
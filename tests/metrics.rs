@@ -0,0 +1,81 @@
+use kvs::command::Request;
+use kvs::metrics::MetricsExporter;
+use kvs::server::KvsServer;
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvsClient, Result};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn scrape(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"));
+    body.to_owned()
+}
+
+#[test]
+fn exports_request_and_store_metrics() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4170".parse().unwrap();
+    let metrics_addr: SocketAddr = "127.0.0.1:4171".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let mut server = KvsServer::new(
+        store.clone(),
+        SharedQueueThreadPool::new(2)?,
+        slog::Logger::root(slog::Discard, slog::o!()),
+    );
+    let handle = server.shutdown_handle();
+    let mut exporter = MetricsExporter::new(
+        store,
+        server.metrics(),
+        slog::Logger::root(slog::Discard, slog::o!()),
+    );
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::spawn(move || exporter.start(metrics_addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    let client = KvsClient::new(addr);
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+    client.request(Request::Set("key1".to_owned(), "value2".to_owned()))?;
+    client.request(Request::Get("key1".to_owned()))?;
+    client.request(Request::Get("key2".to_owned()))?;
+
+    let metrics = scrape(metrics_addr);
+    let lines: Vec<&str> = metrics.lines().collect();
+    for expected in &[
+        "kvs_requests_total{type=\"set\",outcome=\"ok\"} 2",
+        "kvs_requests_total{type=\"get\",outcome=\"ok\"} 1",
+        "kvs_requests_total{type=\"get\",outcome=\"not_found\"} 1",
+        "kvs_request_duration_seconds_bucket{type=\"get\",le=\"+Inf\"} 2",
+        "kvs_request_duration_seconds_count{type=\"set\"} 2",
+        "kvs_keys 1",
+        "kvs_generations 1",
+        "kvs_compactions_total 0",
+        "kvs_thread_pool_queued_jobs 0",
+        "kvs_thread_pool_jobs_total{outcome=\"panicked\"} 0",
+        "# TYPE kvs_request_duration_seconds histogram",
+    ] {
+        assert!(
+            lines.contains(expected),
+            "missing {} in\n{}",
+            expected,
+            metrics
+        );
+    }
+    let value = |name: &str| -> u64 {
+        let line = lines.iter().find(|line| line.starts_with(name)).unwrap();
+        line[name.len() + 1..].parse().unwrap()
+    };
+    assert!(value("kvs_read_bytes_total") > 0);
+    assert!(value("kvs_written_bytes_total") > 0);
+    assert!(value("kvs_dead_bytes") > 0);
+
+    handle.shutdown();
+    Ok(())
+}
@@ -0,0 +1,647 @@
+use kvs::command::{Admin, Request, Response};
+use kvs::rate_limit::RateLimit;
+use kvs::server::{KvsServer, Limits, ServerConfig};
+use kvs::thread_pool::{
+    NaiveThreadPool, OverflowPolicy, PoolConfig, RayonThreadPool, SharedQueueThreadPool,
+    ThreadPool, WorkStealingThreadPool,
+};
+use kvs::{Address, Error, KvStore, KvsClient, KvsEngine, Result, ShutdownHandle};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn logger() -> slog::Logger {
+    slog::Logger::root(slog::Discard, slog::o!())
+}
+
+#[test]
+fn shutdown_stops_accepting_and_flushes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4120".parse().unwrap();
+    let mut server = KvsServer::new(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        logger(),
+    );
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.start(addr));
+    thread::sleep(Duration::from_millis(200));
+
+    KvsClient::send(Request::Set("key1".to_owned(), "value1".to_owned()), addr)?;
+    handle.shutdown();
+    server.join().unwrap()?;
+
+    assert!(TcpStream::connect(addr).is_err());
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn shutdown_gives_up_on_stuck_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = "127.0.0.1:4121";
+    let config = ServerConfig {
+        drain_timeout: Duration::from_millis(300),
+        ..ServerConfig::default()
+    };
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        logger(),
+        config,
+    );
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.start(addr));
+    thread::sleep(Duration::from_millis(200));
+
+    // Connects but never sends a request, pinning a worker
+    let _idle = TcpStream::connect(addr)?;
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    handle.shutdown();
+    server.join().unwrap()?;
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_secs(5));
+    Ok(())
+}
+
+#[test]
+fn shutdown_with_an_unbounded_drain_timeout() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4189".parse().unwrap();
+    let config = ServerConfig {
+        drain_timeout: Duration::MAX,
+        ..ServerConfig::default()
+    };
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        logger(),
+        config,
+    );
+    let handle = server.shutdown_handle();
+    let server = thread::spawn(move || server.start(addr));
+    thread::sleep(Duration::from_millis(200));
+
+    KvsClient::send(Request::Set("key1".to_owned(), "value1".to_owned()), addr)?;
+    handle.shutdown();
+    server.join().unwrap()?;
+    Ok(())
+}
+
+#[test]
+fn serves_tcp_and_unix_socket_together() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let tcp: Address = "127.0.0.1:4122".parse().unwrap();
+    let unix = Address::Unix(temp_dir.path().join("kvs.sock"));
+    let config = ServerConfig {
+        unix_mode: Some(0o600),
+        ..ServerConfig::default()
+    };
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        logger(),
+        config,
+    );
+    let handle = server.shutdown_handle();
+    let addresses = vec![tcp.clone(), unix.clone()];
+    let server = thread::spawn(move || server.start_on(&addresses));
+    thread::sleep(Duration::from_millis(200));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(temp_dir.path().join("kvs.sock"))?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // The directory the socket was bound in before being moved into place is gone
+        let leftovers: Vec<_> = std::fs::read_dir(temp_dir.path())?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<std::io::Result<_>>()?;
+        assert!(leftovers
+            .iter()
+            .all(|name| !name.to_string_lossy().starts_with(".kvs")));
+    }
+
+    KvsClient::send(
+        Request::Set("key1".to_owned(), "value1".to_owned()),
+        unix.clone(),
+    )?;
+    assert_eq!(
+        KvsClient::send(Request::Get("key1".to_owned()), tcp)?,
+        Some("value1".to_owned())
+    );
+
+    handle.shutdown();
+    server.join().unwrap()?;
+    // The socket file is cleaned up on shutdown
+    assert!(!temp_dir.path().join("kvs.sock").exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_never_replaces_other_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("kvs.sock");
+    std::fs::write(&path, "not a socket")?;
+    let mut server = KvsServer::new(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        logger(),
+    );
+
+    match server.start_on(&[Address::Unix(path.clone())]) {
+        Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::AddrInUse),
+        other => panic!("expected AddrInUse, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(std::fs::read_to_string(&path)?, "not a socket");
+    Ok(())
+}
+
+fn start_with(
+    addr: SocketAddr,
+    temp_dir: &TempDir,
+    threads: u32,
+    config: ServerConfig,
+) -> ShutdownHandle {
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path()).unwrap(),
+        SharedQueueThreadPool::new(threads).unwrap(),
+        logger(),
+        config,
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+    handle
+}
+
+fn start_limited(addr: SocketAddr, temp_dir: &TempDir, limits: Limits) -> ShutdownHandle {
+    let config = ServerConfig {
+        limits,
+        ..ServerConfig::default()
+    };
+    start_with(addr, temp_dir, 2, config)
+}
+
+#[test]
+fn rejects_oversized_requests() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4123".parse().unwrap();
+    let limits = Limits {
+        max_frame_size: 1024,
+        max_key_size: 8,
+        max_value_size: 16,
+        ..Limits::default()
+    };
+    let handle = start_limited(addr, &temp_dir, limits);
+
+    let long_key = Request::Set("k".repeat(9), "value".to_owned());
+    assert!(matches!(
+        KvsClient::send(long_key, addr),
+        Err(Error::Rejected(_))
+    ));
+    let long_value = Request::Set("key".to_owned(), "v".repeat(17));
+    assert!(matches!(
+        KvsClient::send(long_value, addr),
+        Err(Error::Rejected(_))
+    ));
+    // Too large to even read, so the server may cut the connection before the reply lands
+    let huge = Request::Set("key".to_owned(), "v".repeat(4096));
+    assert!(KvsClient::send(huge, addr).is_err());
+
+    KvsClient::send(Request::Set("key".to_owned(), "value".to_owned()), addr)?;
+    assert_eq!(
+        KvsClient::send(Request::Get("key".to_owned()), addr)?,
+        Some("value".to_owned())
+    );
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn rejects_connections_over_the_limit() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4124".parse().unwrap();
+    let limits = Limits {
+        max_connections: Some(1),
+        ..Limits::default()
+    };
+    let handle = start_limited(addr, &temp_dir, limits);
+
+    let idle = TcpStream::connect(addr)?;
+    thread::sleep(Duration::from_millis(100));
+    assert!(matches!(
+        KvsClient::send(Request::Get("key".to_owned()), addr),
+        Err(Error::Rejected(_))
+    ));
+
+    drop(idle);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(KvsClient::send(Request::Get("key".to_owned()), addr)?, None);
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn closes_idle_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4125".parse().unwrap();
+    let limits = Limits {
+        idle_timeout: Some(Duration::from_millis(200)),
+        ..Limits::default()
+    };
+    let handle = start_limited(addr, &temp_dir, limits);
+
+    let mut idle = TcpStream::connect(addr)?;
+    idle.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let started = Instant::now();
+    // The server hangs up without replying
+    assert_eq!(idle.read(&mut [0; 1])?, 0);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn refuses_other_protocol_versions() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4187".parse().unwrap();
+    let handle = start_limited(addr, &temp_dir, Limits::default());
+
+    let next_version = kvs::PROTOCOL_VERSION + 1;
+    // A header from a later version, and a bare bincode `Request::Get` from before versioning
+    let frames: [&[u8]; 2] = [
+        &[b'K', next_version, 0, 0, 0, 0],
+        &[0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, b'k', b'e', b'y'],
+    ];
+    for frame in frames.iter() {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.write_all(frame)?;
+        // The server hangs up, possibly resetting the connection over the
+        // unread bytes, rather than misreading the request
+        match stream.read(&mut [0; 1]) {
+            Ok(read) => assert_eq!(read, 0),
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+        }
+    }
+
+    assert_eq!(KvsClient::send(Request::Get("key".to_owned()), addr)?, None);
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn rate_limits_each_client() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4126".parse().unwrap();
+    let config = ServerConfig {
+        rate_limit: Some(RateLimit {
+            per_second: 5.0,
+            burst: 2,
+        }),
+        ..ServerConfig::default()
+    };
+    let handle = start_with(addr, &temp_dir, 2, config);
+
+    KvsClient::send(Request::Get("key".to_owned()), addr)?;
+    KvsClient::send(Request::Get("key".to_owned()), addr)?;
+    match KvsClient::send(Request::Get("key".to_owned()), addr) {
+        Err(Error::Overloaded(retry_after)) => assert!(retry_after <= Duration::from_millis(200)),
+        other => panic!("expected to be rate limited, got {:?}", other),
+    }
+
+    // Backing off for as long as the server asks gets the request through
+    let client = KvsClient::builder(addr).overload_retries(3).build()?;
+    assert_eq!(client.request(Request::Get("key".to_owned()))?, None);
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn sheds_load_when_the_backlog_is_full() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4127".parse().unwrap();
+    let limits = Limits {
+        max_backlog: Some(1),
+        ..Limits::default()
+    };
+    let config = ServerConfig {
+        limits,
+        ..ServerConfig::default()
+    };
+    let handle = start_with(addr, &temp_dir, 1, config);
+
+    // One connection pins the only thread and the next waits behind it
+    let busy = TcpStream::connect(addr)?;
+    let queued = TcpStream::connect(addr)?;
+    thread::sleep(Duration::from_millis(100));
+    assert!(matches!(
+        KvsClient::send(Request::Get("key".to_owned()), addr),
+        Err(Error::Overloaded(_))
+    ));
+
+    drop(busy);
+    drop(queued);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(KvsClient::send(Request::Get("key".to_owned()), addr)?, None);
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn multi_key_requests() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4128".parse().unwrap();
+    let handle = start_with(addr, &temp_dir, 2, ServerConfig::default());
+    let client = KvsClient::new(addr);
+
+    client.multi_set(vec![
+        ("key1".to_owned(), "value1".to_owned()),
+        ("key2".to_owned(), "value2".to_owned()),
+    ])?;
+    assert_eq!(
+        client.multi_get(vec![
+            "key1".to_owned(),
+            "key3".to_owned(),
+            "key2".to_owned()
+        ])?,
+        vec![Some("value1".to_owned()), None, Some("value2".to_owned())]
+    );
+    assert_eq!(
+        client.multi_remove(vec!["key1".to_owned(), "key3".to_owned()])?,
+        vec![true, false]
+    );
+    assert_eq!(
+        client.multi_get(vec!["key1".to_owned(), "key2".to_owned()])?,
+        vec![None, Some("value2".to_owned())]
+    );
+    handle.shutdown();
+    Ok(())
+}
+
+// Without an ACL, admin requests have to be allowed explicitly
+fn admin_config() -> ServerConfig {
+    ServerConfig {
+        allow_admin_without_acl: true,
+        ..ServerConfig::default()
+    }
+}
+
+#[test]
+fn admin_commands_need_an_acl_or_opting_in() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4188".parse().unwrap();
+    let handle = start_with(addr, &temp_dir, 2, ServerConfig::default());
+
+    let client = KvsClient::new(addr);
+    for &admin in [Admin::Stats, Admin::Compact, Admin::Shutdown].iter() {
+        assert!(matches!(
+            client.call(Request::Admin(admin)),
+            Err(Error::Unauthorized(_))
+        ));
+    }
+    // Everything else still runs, and the refused shutdown left the server up
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+    assert_eq!(
+        client.request(Request::Get("key1".to_owned()))?,
+        Some("value1".to_owned())
+    );
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn admin_commands() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4129".parse().unwrap();
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        logger(),
+        admin_config(),
+    );
+    let server = thread::spawn(move || server.start(addr));
+    thread::sleep(Duration::from_millis(200));
+    let client = KvsClient::new(addr);
+
+    for i in 0..100 {
+        client.request(Request::Set("key1".to_owned(), i.to_string()))?;
+    }
+    client.request(Request::Set("key2".to_owned(), "value2".to_owned()))?;
+    assert!(matches!(
+        client.call(Request::Admin(Admin::Compact))?,
+        Response::OK(_)
+    ));
+    assert!(matches!(
+        client.call(Request::Admin(Admin::Flush))?,
+        Response::OK(_)
+    ));
+
+    let entries = |admin| match client.call(Request::Admin(admin)) {
+        Ok(Response::Entries(entries)) => entries,
+        _ => panic!("expected entries for {:?}", admin),
+    };
+    let stats = entries(Admin::Stats);
+    assert!(stats.contains(&("keys".to_owned(), "2".to_owned())));
+    assert!(stats.contains(&("connections".to_owned(), "1".to_owned())));
+    let config = entries(Admin::Config);
+    assert!(config.contains(&("max_connections".to_owned(), "1024".to_owned())));
+
+    match client.call(Request::Admin(Admin::Connections))? {
+        Response::Connections(connections) => {
+            assert_eq!(connections.len(), 1);
+            assert_eq!(connections[0].principal, None);
+            assert!(connections[0].requests >= 105);
+        }
+        _ => panic!("expected connections"),
+    }
+
+    assert!(matches!(
+        client.call(Request::Admin(Admin::Shutdown))?,
+        Response::OK(_)
+    ));
+    server.join().unwrap()?;
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("99".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn reloads_limits_without_dropping_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4180".parse().unwrap();
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+        logger(),
+        admin_config(),
+    );
+    let handle = server.shutdown_handle();
+    let reload = server.reload_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    let client = KvsClient::new(addr);
+    let connections = || match client.call(Request::Admin(Admin::Connections)) {
+        Ok(Response::Connections(connections)) => connections
+            .into_iter()
+            .map(|connection| connection.id)
+            .collect::<Vec<_>>(),
+        _ => panic!("expected connections"),
+    };
+    client.request(Request::Set("key".to_owned(), "v".repeat(17)))?;
+    let before = connections();
+
+    reload.reload(&ServerConfig {
+        limits: Limits {
+            max_value_size: 16,
+            ..Limits::default()
+        },
+        ..admin_config()
+    })?;
+    assert!(matches!(
+        client.request(Request::Set("key".to_owned(), "v".repeat(17))),
+        Err(Error::Rejected(_))
+    ));
+    client.request(Request::Set("key".to_owned(), "v".repeat(16)))?;
+    assert_eq!(connections(), before);
+    match client.call(Request::Admin(Admin::Config))? {
+        Response::Entries(config) => {
+            assert!(config.contains(&("max_value_size".to_owned(), "16".to_owned())))
+        }
+        _ => panic!("expected entries"),
+    }
+
+    handle.shutdown();
+    Ok(())
+}
+
+// Clients on several threads at once each see their own writes, whatever the pool
+fn serves_concurrent_clients<P: ThreadPool + Send + 'static>(addr: SocketAddr) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path())?,
+        P::new(4)?,
+        logger(),
+        admin_config(),
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    let clients: Vec<_> = (0..8)
+        .map(|client| {
+            thread::spawn(move || -> Result<()> {
+                for i in 0..20 {
+                    let key = format!("client{}-key{}", client, i);
+                    KvsClient::send(Request::Set(key.clone(), i.to_string()), addr)?;
+                    assert_eq!(
+                        KvsClient::send(Request::Get(key), addr)?,
+                        Some(i.to_string())
+                    );
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap()?;
+    }
+
+    let client = KvsClient::new(addr);
+    match client.call(Request::Admin(Admin::Stats))? {
+        Response::Entries(stats) => assert!(stats.contains(&("keys".to_owned(), "160".to_owned()))),
+        _ => panic!("expected entries"),
+    }
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn naive_pool_serves_concurrent_clients() -> Result<()> {
+    serves_concurrent_clients::<NaiveThreadPool>("127.0.0.1:4181".parse().unwrap())
+}
+
+#[test]
+fn shared_queue_pool_serves_concurrent_clients() -> Result<()> {
+    serves_concurrent_clients::<SharedQueueThreadPool>("127.0.0.1:4182".parse().unwrap())
+}
+
+#[test]
+fn rayon_pool_serves_concurrent_clients() -> Result<()> {
+    serves_concurrent_clients::<RayonThreadPool>("127.0.0.1:4183".parse().unwrap())
+}
+
+#[test]
+fn work_stealing_pool_serves_concurrent_clients() -> Result<()> {
+    serves_concurrent_clients::<WorkStealingThreadPool>("127.0.0.1:4186".parse().unwrap())
+}
+
+#[test]
+fn rayon_pool_serves_connections_in_parallel() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4184".parse().unwrap();
+    let mut server = KvsServer::new(
+        KvStore::open(temp_dir.path())?,
+        RayonThreadPool::new(2)?,
+        logger(),
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    // An idle connection holds one worker without blocking the accept loop
+    let idle = TcpStream::connect(addr)?;
+    thread::sleep(Duration::from_millis(100));
+    let started = Instant::now();
+    KvsClient::send(Request::Set("key1".to_owned(), "value1".to_owned()), addr)?;
+    assert_eq!(
+        KvsClient::send(Request::Get("key1".to_owned()), addr)?,
+        Some("value1".to_owned())
+    );
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    drop(idle);
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn answers_overloaded_when_the_pool_queue_is_full() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4185".parse().unwrap();
+    let mut server = KvsServer::new(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::with_queue(1, 1, OverflowPolicy::Reject, PoolConfig::default())?,
+        logger(),
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    // One connection pins the only thread and the next fills the queue
+    let busy = TcpStream::connect(addr)?;
+    let queued = TcpStream::connect(addr)?;
+    thread::sleep(Duration::from_millis(100));
+    assert!(matches!(
+        KvsClient::send(Request::Get("key".to_owned()), addr),
+        Err(Error::Overloaded(_))
+    ));
+
+    drop(busy);
+    drop(queued);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(KvsClient::send(Request::Get("key".to_owned()), addr)?, None);
+    handle.shutdown();
+    Ok(())
+}
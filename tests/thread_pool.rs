@@ -1,8 +1,11 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use kvs::thread_pool::*;
-use kvs::Result;
+use kvs::{Error, Result};
 
 use crossbeam_utils::sync::WaitGroup;
 
@@ -64,7 +67,437 @@ fn rayon_thread_pool_spawn_counter() -> Result<()> {
     spawn_counter(pool)
 }
 
+#[test]
+fn work_stealing_thread_pool_spawn_counter() -> Result<()> {
+    let pool = WorkStealingThreadPool::new(4)?;
+    spawn_counter(pool)
+}
+
 #[test]
 fn shared_queue_thread_pool_panic_task() -> Result<()> {
     spawn_panic_task::<SharedQueueThreadPool>()
 }
+
+#[test]
+fn work_stealing_thread_pool_panic_task() -> Result<()> {
+    spawn_panic_task::<WorkStealingThreadPool>()
+}
+
+// Jobs already spawned run to completion before `shutdown` returns, and later ones never run
+fn shutdown_drains_jobs<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(2)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..10 {
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    assert_eq!(pool.shutdown(Duration::from_secs(5)), 0);
+    assert_eq!(counter.load(Ordering::SeqCst), 10);
+
+    let counter_after = Arc::clone(&counter);
+    pool.spawn(move || {
+        counter_after.fetch_add(1, Ordering::SeqCst);
+    });
+    pool.join();
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(counter.load(Ordering::SeqCst), 10);
+    Ok(())
+}
+
+// Jobs still queued or running when the timeout passes are reported
+fn shutdown_reports_abandoned_jobs<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(1)?;
+    for _ in 0..4 {
+        pool.spawn(|| thread::sleep(Duration::from_millis(300)));
+    }
+
+    let started = Instant::now();
+    assert_eq!(pool.shutdown(Duration::from_millis(50)), 4);
+    assert!(started.elapsed() < Duration::from_millis(250));
+    Ok(())
+}
+
+#[test]
+fn naive_thread_pool_shutdown() -> Result<()> {
+    shutdown_drains_jobs::<NaiveThreadPool>()?;
+    shutdown_reports_abandoned_jobs::<NaiveThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_shutdown() -> Result<()> {
+    shutdown_drains_jobs::<SharedQueueThreadPool>()?;
+    shutdown_reports_abandoned_jobs::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_shutdown() -> Result<()> {
+    shutdown_drains_jobs::<WorkStealingThreadPool>()?;
+    shutdown_reports_abandoned_jobs::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_shutdown() -> Result<()> {
+    shutdown_drains_jobs::<RayonThreadPool>()?;
+    shutdown_reports_abandoned_jobs::<RayonThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_spawn_does_not_block() -> Result<()> {
+    let pool = RayonThreadPool::new(1)?;
+    let started = Instant::now();
+    pool.spawn(|| thread::sleep(Duration::from_millis(300)));
+    assert!(started.elapsed() < Duration::from_millis(100));
+    pool.join();
+    Ok(())
+}
+
+#[test]
+fn rayon_thread_pool_survives_panicking_jobs() -> Result<()> {
+    let pool = RayonThreadPool::new(2)?;
+    for _ in 0..10 {
+        pool.spawn(|| {
+            panic_control::disable_hook_in_current_thread();
+            panic!();
+        });
+    }
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..10 {
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    assert_eq!(pool.shutdown(Duration::from_secs(5)), 0);
+    assert_eq!(counter.load(Ordering::SeqCst), 10);
+    Ok(())
+}
+
+#[test]
+fn shutdown_survives_panicking_jobs() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    for _ in 0..10 {
+        pool.spawn(|| {
+            panic_control::disable_hook_in_current_thread();
+            panic!();
+        });
+    }
+    assert_eq!(pool.shutdown(Duration::from_secs(5)), 0);
+    Ok(())
+}
+
+// Pins one of the pool's threads until the returned sender is dropped
+fn occupy<P: ThreadPool>(pool: &P) -> mpsc::Sender<()> {
+    let (release, released) = mpsc::channel::<()>();
+    pool.spawn(move || {
+        let _ = released.recv();
+    });
+    thread::sleep(Duration::from_millis(50));
+    release
+}
+
+// Records the order jobs run in
+fn record(pool: &SharedQueueThreadPool, ran: &Arc<Mutex<Vec<usize>>>, job: usize) -> Result<()> {
+    let ran = Arc::clone(ran);
+    pool.try_spawn(move || ran.lock().unwrap().push(job))
+}
+
+#[test]
+fn bounded_queue_rejects_when_full() -> Result<()> {
+    let pool =
+        SharedQueueThreadPool::with_queue(1, 2, OverflowPolicy::Reject, PoolConfig::default())?;
+    let release = occupy(&pool);
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    record(&pool, &ran, 1)?;
+    record(&pool, &ran, 2)?;
+    assert!(matches!(record(&pool, &ran, 3), Err(Error::QueueFull)));
+
+    drop(release);
+    pool.join();
+    assert_eq!(*ran.lock().unwrap(), vec![1, 2]);
+    Ok(())
+}
+
+#[test]
+fn bounded_queue_drops_the_oldest_job() -> Result<()> {
+    let pool =
+        SharedQueueThreadPool::with_queue(1, 2, OverflowPolicy::DropOldest, PoolConfig::default())?;
+    let release = occupy(&pool);
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    for job in 1..=4 {
+        record(&pool, &ran, job)?;
+    }
+
+    drop(release);
+    pool.join();
+    assert_eq!(*ran.lock().unwrap(), vec![3, 4]);
+    Ok(())
+}
+
+#[test]
+fn bounded_queue_blocks_until_there_is_room() -> Result<()> {
+    let pool = Arc::new(SharedQueueThreadPool::with_queue(
+        1,
+        1,
+        OverflowPolicy::Block,
+        PoolConfig::default(),
+    )?);
+    let release = occupy(&*pool);
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    record(&pool, &ran, 1)?;
+
+    let blocked = {
+        let pool = Arc::clone(&pool);
+        let ran = Arc::clone(&ran);
+        thread::spawn(move || record(&pool, &ran, 2))
+    };
+    thread::sleep(Duration::from_millis(100));
+    assert!(!blocked.is_finished());
+
+    drop(release);
+    blocked.join().unwrap()?;
+    pool.join();
+    assert_eq!(*ran.lock().unwrap(), vec![1, 2]);
+    Ok(())
+}
+
+fn try_spawn_after_shutdown<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(2)?;
+    pool.try_spawn(|| ())?;
+    pool.join();
+    assert!(matches!(pool.try_spawn(|| ()), Err(Error::PoolShutDown)));
+    Ok(())
+}
+
+#[test]
+fn try_spawn_fails_after_shutdown() -> Result<()> {
+    try_spawn_after_shutdown::<NaiveThreadPool>()?;
+    try_spawn_after_shutdown::<SharedQueueThreadPool>()?;
+    try_spawn_after_shutdown::<WorkStealingThreadPool>()?;
+    try_spawn_after_shutdown::<RayonThreadPool>()
+}
+
+fn reports_stats<P: ThreadPool>() -> Result<()> {
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let config = PoolConfig {
+        panic_handler: Some({
+            let handled = Arc::clone(&handled);
+            Arc::new(move |payload: &(dyn std::any::Any + Send)| {
+                handled
+                    .lock()
+                    .unwrap()
+                    .push(panic_message(payload).to_owned())
+            })
+        }),
+        ..PoolConfig::default()
+    };
+    let pool = P::with_config(2, config)?;
+
+    let release = occupy(&pool);
+    let stats = pool.stats();
+    assert_eq!(stats.active_workers, 1);
+    assert_eq!(stats.queued_jobs, 0);
+    drop(release);
+
+    for i in 0..5 {
+        pool.spawn(move || {
+            if i % 2 == 0 {
+                panic_control::disable_hook_in_current_thread();
+                panic!("job {}", i);
+            }
+        });
+    }
+    pool.join();
+
+    let stats = pool.stats();
+    assert_eq!(stats.active_workers, 0);
+    assert_eq!(stats.queued_jobs, 0);
+    assert_eq!(stats.completed_jobs, 3);
+    assert_eq!(stats.panicked_jobs, 3);
+    let mut handled = handled.lock().unwrap().clone();
+    handled.sort();
+    assert_eq!(handled, vec!["job 0", "job 2", "job 4"]);
+    Ok(())
+}
+
+#[test]
+fn thread_pool_stats() -> Result<()> {
+    reports_stats::<NaiveThreadPool>()?;
+    reports_stats::<SharedQueueThreadPool>()?;
+    reports_stats::<RayonThreadPool>()?;
+    reports_stats::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn stats_count_idle_and_queued() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    let first = occupy(&pool);
+    assert_eq!(pool.stats().idle_workers, 1);
+    let second = occupy(&pool);
+    let third = occupy(&pool);
+    let stats = pool.stats();
+    assert_eq!(stats.active_workers, 2);
+    assert_eq!(stats.idle_workers, 0);
+    assert_eq!(stats.queued_jobs, 1);
+
+    drop((first, second, third));
+    pool.join();
+    assert_eq!(pool.stats().completed_jobs, 3);
+    Ok(())
+}
+
+fn elastic_pool(min_threads: u32, max_threads: u32) -> Result<SharedQueueThreadPool> {
+    let options = SharedQueueOptions {
+        min_threads,
+        max_threads,
+        idle_timeout: Duration::from_millis(200),
+        ..SharedQueueOptions::fixed(max_threads)
+    };
+    SharedQueueThreadPool::with_options(options, PoolConfig::default())
+}
+
+#[test]
+fn shared_queue_grows_under_load_and_shrinks_when_idle() -> Result<()> {
+    let pool = elastic_pool(1, 4)?;
+    assert_eq!(pool.stats().workers(), 1);
+
+    let busy: Vec<_> = (0..5).map(|_| occupy(&pool)).collect();
+    let stats = pool.stats();
+    assert_eq!(stats.workers(), 4);
+    assert_eq!(stats.active_workers, 4);
+    assert_eq!(stats.queued_jobs, 1);
+
+    drop(busy);
+    thread::sleep(Duration::from_millis(600));
+    let stats = pool.stats();
+    assert_eq!(stats.workers(), 1);
+    assert_eq!(stats.completed_jobs, 5);
+    assert_eq!(pool.shutdown(Duration::from_secs(5)), 0);
+    Ok(())
+}
+
+#[test]
+fn shared_queue_grows_from_no_threads() -> Result<()> {
+    let pool = elastic_pool(0, 2)?;
+    assert_eq!(pool.stats().workers(), 0);
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let counter_job = Arc::clone(&counter);
+    pool.spawn(move || {
+        counter_job.fetch_add(1, Ordering::SeqCst);
+    });
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(pool.stats().workers(), 0);
+    pool.join();
+    Ok(())
+}
+
+#[test]
+fn shared_queue_resize() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    pool.resize(4)?;
+    assert_eq!(pool.stats().workers(), 4);
+
+    // Busy threads over the new size finish their jobs before going
+    let busy: Vec<_> = (0..3).map(|_| occupy(&pool)).collect();
+    pool.resize(1)?;
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(pool.stats().workers(), 3);
+    drop(busy);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(pool.stats().workers(), 1);
+
+    assert!(matches!(pool.resize(0), Err(Error::Unsupported(_))));
+    pool.join();
+    Ok(())
+}
+
+#[test]
+fn fixed_pools_refuse_to_resize() -> Result<()> {
+    assert!(matches!(
+        RayonThreadPool::new(2)?.resize(4),
+        Err(Error::Unsupported(_))
+    ));
+    assert!(matches!(
+        WorkStealingThreadPool::new(2)?.resize(4),
+        Err(Error::Unsupported(_))
+    ));
+    Ok(())
+}
+
+fn handles_return_results_and_panics<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(2)?;
+    let sum = pool.spawn_with_handle(|| (1..=10).sum::<u32>())?;
+    let panicked = pool.spawn_with_handle(|| {
+        panic_control::disable_hook_in_current_thread();
+        panic!("boom");
+    })?;
+
+    assert_eq!(sum.join().unwrap(), 55);
+    match panicked.join() {
+        Err(JobError::Panicked(payload)) => assert_eq!(panic_message(&*payload), "boom"),
+        _ => panic!("expected the panic"),
+    }
+    pool.join();
+    assert_eq!(pool.stats().panicked_jobs, 1);
+    Ok(())
+}
+
+#[test]
+fn job_handles() -> Result<()> {
+    handles_return_results_and_panics::<NaiveThreadPool>()?;
+    handles_return_results_and_panics::<SharedQueueThreadPool>()?;
+    handles_return_results_and_panics::<RayonThreadPool>()?;
+    handles_return_results_and_panics::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn cancels_jobs_that_have_not_started() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(1)?;
+    let release = occupy(&pool);
+    let ran = Arc::new(AtomicUsize::new(0));
+    let ran_job = Arc::clone(&ran);
+    let queued = pool.spawn_with_handle(move || {
+        ran_job.fetch_add(1, Ordering::SeqCst);
+    })?;
+
+    assert!(!queued.is_finished());
+    assert!(queued.cancel());
+    assert!(queued.is_finished());
+    assert!(matches!(queued.join(), Err(JobError::Cancelled)));
+
+    drop(release);
+    let started = pool.spawn_with_handle(|| thread::sleep(Duration::from_millis(100)))?;
+    thread::sleep(Duration::from_millis(50));
+    assert!(!started.cancel());
+    started.join().unwrap();
+
+    pool.join();
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+    Ok(())
+}
+
+#[test]
+fn handles_of_dropped_jobs_say_so() -> Result<()> {
+    let pool =
+        SharedQueueThreadPool::with_queue(1, 1, OverflowPolicy::DropOldest, PoolConfig::default())?;
+    let release = occupy(&pool);
+    let oldest = pool.spawn_with_handle(|| 1)?;
+    let newest = pool.spawn_with_handle(|| 2)?;
+    assert!(matches!(oldest.join(), Err(JobError::Dropped)));
+
+    drop(release);
+    assert_eq!(newest.join().unwrap(), 2);
+    pool.join();
+    assert!(matches!(
+        pool.spawn_with_handle(|| 3),
+        Err(Error::PoolShutDown)
+    ));
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use kvs::command::Request;
+use kvs::server::{KvsServer, ServerConfig};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::tls::{ClientTlsConfig, ServerTlsConfig};
+use kvs::{KvStore, KvsClient, Result, ShutdownHandle};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Writes `ca.pem` plus server and client certificates and keys signed by it
+fn write_certs(dir: &Path) {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+    fs::write(dir.join("ca.pem"), ca_cert.pem()).unwrap();
+
+    for name in &["server", "client"] {
+        let key = KeyPair::generate().unwrap();
+        let params =
+            CertificateParams::new(vec!["127.0.0.1".to_owned(), "localhost".to_owned()]).unwrap();
+        let cert = params.signed_by(&key, &ca_cert, &ca_key).unwrap();
+        fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+        fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+    }
+}
+
+fn start_server(addr: SocketAddr, dir: &Path, verify_clients: bool) -> ShutdownHandle {
+    let config = ServerConfig {
+        tls: Some(ServerTlsConfig {
+            cert: dir.join("server.pem"),
+            key: dir.join("server.key"),
+            client_ca: if verify_clients {
+                Some(dir.join("ca.pem"))
+            } else {
+                None
+            },
+        }),
+        ..ServerConfig::default()
+    };
+    let mut server = KvsServer::with_config(
+        KvStore::open(dir).unwrap(),
+        SharedQueueThreadPool::new(2).unwrap(),
+        slog::Logger::root(slog::Discard, slog::o!()),
+        config,
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+    handle
+}
+
+fn client_tls(dir: &Path, with_cert: bool) -> ClientTlsConfig {
+    ClientTlsConfig {
+        ca: dir.join("ca.pem"),
+        cert: if with_cert {
+            Some(dir.join("client.pem"))
+        } else {
+            None
+        },
+        key: if with_cert {
+            Some(dir.join("client.key"))
+        } else {
+            None
+        },
+        server_name: None,
+    }
+}
+
+#[test]
+fn tls_round_trip() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_certs(temp_dir.path());
+    let addr: SocketAddr = "127.0.0.1:4130".parse().unwrap();
+    let handle = start_server(addr, temp_dir.path(), false);
+
+    let client = KvsClient::builder(addr)
+        .tls(client_tls(temp_dir.path(), false))
+        .build()?;
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+    assert_eq!(
+        client.request(Request::Get("key1".to_owned()))?,
+        Some("value1".to_owned())
+    );
+
+    // A plaintext client can't talk to a TLS server
+    assert!(KvsClient::send(Request::Get("key1".to_owned()), addr).is_err());
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn tls_rejects_server_signed_by_unknown_ca() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_certs(temp_dir.path());
+    let addr: SocketAddr = "127.0.0.1:4131".parse().unwrap();
+    let handle = start_server(addr, temp_dir.path(), false);
+
+    // Trust an unrelated CA instead of the one that signed the server certificate
+    let other_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_certs(other_dir.path());
+    let client = KvsClient::builder(addr)
+        .tls(client_tls(other_dir.path(), false))
+        .build()?;
+    assert!(client.request(Request::Get("key1".to_owned())).is_err());
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn mutual_tls_requires_client_certificate() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_certs(temp_dir.path());
+    let addr: SocketAddr = "127.0.0.1:4132".parse().unwrap();
+    let handle = start_server(addr, temp_dir.path(), true);
+
+    let anonymous = KvsClient::builder(addr)
+        .tls(client_tls(temp_dir.path(), false))
+        .build()?;
+    assert!(anonymous
+        .request(Request::Set("key1".to_owned(), "value1".to_owned()))
+        .is_err());
+
+    let client = KvsClient::builder(addr)
+        .tls(client_tls(temp_dir.path(), true))
+        .build()?;
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+    assert_eq!(
+        client.request(Request::Get("key1".to_owned()))?,
+        Some("value1".to_owned())
+    );
+
+    handle.shutdown();
+    Ok(())
+}
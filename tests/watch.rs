@@ -0,0 +1,109 @@
+use kvs::command::{Request, Watch};
+use kvs::engines::{Event, WATCHER_CAPACITY};
+use kvs::server::{KvsServer, ServerConfig};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvsClient, KvsEngine, Result, ShutdownHandle, SledKvsEngine};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn start_server(addr: SocketAddr, temp_dir: &TempDir) -> ShutdownHandle {
+    let mut server = KvsServer::with_config(
+        KvStore::open(temp_dir.path()).unwrap(),
+        SharedQueueThreadPool::new(4).unwrap(),
+        slog::Logger::root(slog::Discard, slog::o!()),
+        ServerConfig::default(),
+    );
+    let handle = server.shutdown_handle();
+    thread::spawn(move || server.start(addr).unwrap());
+    thread::sleep(Duration::from_millis(200));
+    handle
+}
+
+fn event(key: &str, value: Option<&str>, version: u64) -> Event {
+    Event {
+        key: key.to_owned(),
+        value: value.map(str::to_owned),
+        version,
+    }
+}
+
+#[test]
+fn streams_writes_to_watched_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4160".parse().unwrap();
+    let handle = start_server(addr, &temp_dir);
+
+    let client = KvsClient::new(addr);
+    let mut key = client.watch(Watch::Key("config/a".to_owned()))?;
+    let mut prefix = client.watch(Watch::Prefix("config/".to_owned()))?;
+    client.request(Request::Set("config/a".to_owned(), "1".to_owned()))?;
+    client.request(Request::Set("other".to_owned(), "2".to_owned()))?;
+    client.request(Request::Set("config/b".to_owned(), "3".to_owned()))?;
+    client.request(Request::Rm("config/a".to_owned()))?;
+
+    assert_eq!(key.next().unwrap()?, event("config/a", Some("1"), 1));
+    assert_eq!(key.next().unwrap()?, event("config/a", None, 4));
+    assert_eq!(prefix.next().unwrap()?, event("config/a", Some("1"), 1));
+    assert_eq!(prefix.next().unwrap()?, event("config/b", Some("3"), 3));
+    assert_eq!(prefix.next().unwrap()?, event("config/a", None, 4));
+
+    // Watches end with the server rather than holding up its shutdown
+    handle.shutdown();
+    assert!(key.next().is_none());
+    Ok(())
+}
+
+#[test]
+fn watchers_leaving_free_their_connection() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4161".parse().unwrap();
+    let handle = start_server(addr, &temp_dir);
+
+    // More watches come and go than the pool has threads
+    let client = KvsClient::new(addr);
+    for _ in 0..8 {
+        drop(client.watch(Watch::Prefix("".to_owned()))?);
+        thread::sleep(Duration::from_millis(100));
+    }
+    client.request(Request::Set("key1".to_owned(), "value1".to_owned()))?;
+    assert_eq!(
+        client.request(Request::Get("key1".to_owned()))?,
+        Some("value1".to_owned())
+    );
+
+    handle.shutdown();
+    Ok(())
+}
+
+#[test]
+fn sled_publishes_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = SledKvsEngine::new(sled::open(temp_dir.path())?);
+
+    let mut watcher = engine.watch("key".to_owned())?;
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.set("other".to_owned(), "value2".to_owned())?;
+    engine.clone().remove("key1".to_owned())?;
+
+    assert_eq!(watcher.next(), Some(event("key1", Some("value1"), 1)));
+    assert_eq!(watcher.next(), Some(event("key1", None, 3)));
+    Ok(())
+}
+
+#[test]
+fn watchers_that_fall_behind_are_cut_off() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let mut watcher = store.watch("".to_owned())?;
+    for i in 0..=WATCHER_CAPACITY {
+        store.set(format!("key{}", i), "value".to_owned())?;
+    }
+
+    // Writes go ahead without waiting, and the watch ends after the events that fit
+    assert!(watcher.overflowed());
+    assert_eq!(watcher.by_ref().count(), WATCHER_CAPACITY);
+    Ok(())
+}